@@ -2,6 +2,9 @@ use std::ffi::c_void;
 use std::fmt::Debug;
 use std::panic::RefUnwindSafe;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use ext_php_rs::embed::Embed;
 use ext_php_rs::embed::EmbedError;
@@ -10,10 +13,20 @@ use ext_php_rs::embed::ServerVarRegistrar;
 use ext_php_rs::zend::SapiGlobals;
 use pasir_sys::ZEND_RESULT_CODE_FAILURE;
 
+use crate::enter_span;
 use crate::error::ExecutePhpError;
+use crate::error::LastError;
 use crate::ext::SapiRequestInfoExt;
 use crate::free_raw_cstring_mut;
 
+/// Time spent in each phase of a PHP request.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RequestTimings {
+  pub startup: Duration,
+  pub execute: Duration,
+  pub shutdown: Duration,
+}
+
 pub trait ServerContext: Sized + ext_php_rs::embed::ServerContext {
   #[must_use = "losing the pointer will leak memory"]
   fn into_raw(self) -> *mut Self {
@@ -70,10 +83,32 @@ pub trait ServerContext: Sized + ext_php_rs::embed::ServerContext {
   fn register_server_variables(&self, registrar: &mut ServerVarRegistrar);
 
   #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, embed_error_handler), err))]
-  fn execute_php<P, F>(self, script: P, embed_error_handler: F) -> Result<(), ExecutePhpError>
+  fn execute_php<P, F>(self, script: P, embed_error_handler: F) -> Result<RequestTimings, ExecutePhpError>
   where
     P: AsRef<Path> + Debug + RefUnwindSafe,
     F: Fn(EmbedError) + RefUnwindSafe,
+  {
+    // `Embed::run_script` catches the bailouts of the script, so its last error is read here.
+    let bailout = Mutex::new(None);
+    let timings = self.with_request(|| {
+      if let Err(e) = Embed::run_script(&script)
+        && e.is_bailout()
+      {
+        *bailout.lock().unwrap() = Some(LastError::get());
+        embed_error_handler(e);
+      }
+    })?;
+
+    match bailout.into_inner().unwrap() {
+      Some(error) => Err(ExecutePhpError::Bailout(error)),
+      None => Ok(timings),
+    }
+  }
+
+  /// Starts a PHP request for the context, runs `func` within it and shuts the request down.
+  fn with_request<F>(self, func: F) -> Result<RequestTimings, ExecutePhpError>
+  where
+    F: FnMut() + RefUnwindSafe,
   {
     let mut request_info = RequestInfo::default();
     self.init_request_info(&mut request_info);
@@ -83,30 +118,43 @@ pub trait ServerContext: Sized + ext_php_rs::embed::ServerContext {
     sapi_globals.server_context = self.into_raw().cast();
     drop(sapi_globals);
 
-    if unsafe { pasir_sys::php_request_startup() } == ZEND_RESULT_CODE_FAILURE {
+    let mut timings = RequestTimings::default();
+    let started = Instant::now();
+    let startup = {
+      let _span = enter_span!("php_request_startup");
+      unsafe { pasir_sys::php_request_startup() }
+    };
+    timings.startup = started.elapsed();
+    if startup == ZEND_RESULT_CODE_FAILURE {
       return Err(ExecutePhpError::RequestStartupFailed);
     }
 
-    let catch = ext_php_rs::zend::try_catch_first(|| {
-      if let Err(e) = Embed::run_script(&script)
-        && e.is_bailout()
-      {
-        embed_error_handler(e);
-      }
-    });
-
-    #[cfg(php84)]
-    unsafe {
-      pasir_sys::zend_shutdown_strtod()
+    // Spans are entered outside of `try_catch_first`, as a bailout skips the destructors within.
+    let started = Instant::now();
+    let catch = {
+      let _span = enter_span!("php_execute_script");
+      ext_php_rs::zend::try_catch_first(func)
     };
-    unsafe { pasir_sys::php_request_shutdown(std::ptr::null_mut()) };
-    free_raw_cstring_mut!(SapiGlobals::get().request_info, path_translated);
+    timings.execute = started.elapsed();
+    let error = catch.is_err().then(LastError::get);
+
+    let started = Instant::now();
+    {
+      let _span = enter_span!("php_request_shutdown");
+      #[cfg(php84)]
+      unsafe {
+        pasir_sys::zend_shutdown_strtod()
+      };
+      unsafe { pasir_sys::php_request_shutdown(std::ptr::null_mut()) };
+      free_raw_cstring_mut!(SapiGlobals::get().request_info, path_translated);
+    }
+    timings.shutdown = started.elapsed();
 
-    if catch.is_err() {
-      return Err(ExecutePhpError::Bailout);
+    if let Some(error) = error {
+      return Err(ExecutePhpError::Bailout(error));
     }
 
-    Ok(())
+    Ok(timings)
   }
 }
 
@@ -1,13 +1,127 @@
 use std::ffi::NulError;
+use std::fmt::Display;
+use std::fmt::Formatter;
 
+use ext_php_rs::ffi::zend_string;
+use ext_php_rs::zend::ProcessGlobals;
+use pasir_sys::E_COMPILE_ERROR;
+use pasir_sys::E_COMPILE_WARNING;
+use pasir_sys::E_CORE_ERROR;
+use pasir_sys::E_CORE_WARNING;
+use pasir_sys::E_DEPRECATED;
+use pasir_sys::E_ERROR;
+use pasir_sys::E_NOTICE;
+use pasir_sys::E_PARSE;
+use pasir_sys::E_RECOVERABLE_ERROR;
+use pasir_sys::E_USER_DEPRECATED;
+use pasir_sys::E_USER_ERROR;
+use pasir_sys::E_USER_NOTICE;
+use pasir_sys::E_USER_WARNING;
+use pasir_sys::E_WARNING;
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq)]
+#[derive(Clone, Debug, Error, PartialEq)]
 pub enum ExecutePhpError {
   #[error(transparent)]
   InitSapiGlobalsError(#[from] NulError),
   #[error("Request startup failed")]
   RequestStartupFailed,
-  #[error("A bailout occurred during the execution")]
-  Bailout,
+  #[error("A bailout occurred during the execution{}", .0.as_ref().map(|error| format!(": {error}")).unwrap_or_default())]
+  Bailout(Option<LastError>),
+}
+
+/// Last error raised by PHP in the current request, read from its error globals.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LastError {
+  pub error_type: i32,
+  pub message: String,
+  pub file: String,
+  pub line: u32,
+}
+
+impl LastError {
+  /// Reads the last error, which PHP clears when the request shuts down.
+  pub fn get() -> Option<Self> {
+    let globals = ProcessGlobals::get();
+    let message = unsafe { zend_string_lossy(globals.last_error_message) }?;
+    Some(Self {
+      error_type: globals.last_error_type,
+      message,
+      file: unsafe { zend_string_lossy(globals.last_error_file) }.unwrap_or_default(),
+      line: globals.last_error_lineno as u32,
+    })
+  }
+
+  /// Label of the error type, as printed by PHP.
+  pub fn kind(&self) -> &'static str {
+    match self.error_type.cast_unsigned() {
+      E_ERROR | E_CORE_ERROR | E_COMPILE_ERROR | E_USER_ERROR => "Fatal error",
+      E_RECOVERABLE_ERROR => "Recoverable fatal error",
+      E_WARNING | E_CORE_WARNING | E_COMPILE_WARNING | E_USER_WARNING => "Warning",
+      E_PARSE => "Parse error",
+      E_NOTICE | E_USER_NOTICE => "Notice",
+      E_DEPRECATED | E_USER_DEPRECATED => "Deprecated",
+      _ => "Unknown error",
+    }
+  }
+}
+
+impl Display for LastError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "PHP {}: {} in {} on line {}", self.kind(), self.message, self.file, self.line)
+  }
+}
+
+unsafe fn zend_string_lossy(string: *const zend_string) -> Option<String> {
+  let string = unsafe { string.as_ref() }?;
+  let bytes = unsafe { std::slice::from_raw_parts(string.val.as_ptr().cast::<u8>(), string.len) };
+  Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+  use pasir_sys::E_DEPRECATED;
+  use pasir_sys::E_PARSE;
+  use pasir_sys::E_RECOVERABLE_ERROR;
+  use pasir_sys::E_USER_ERROR;
+  use pasir_sys::E_USER_NOTICE;
+  use pasir_sys::E_USER_WARNING;
+  use rstest::rstest;
+
+  use crate::error::ExecutePhpError;
+  use crate::error::LastError;
+
+  #[rstest]
+  #[case(E_USER_ERROR, "Fatal error")]
+  #[case(E_RECOVERABLE_ERROR, "Recoverable fatal error")]
+  #[case(E_USER_WARNING, "Warning")]
+  #[case(E_PARSE, "Parse error")]
+  #[case(E_USER_NOTICE, "Notice")]
+  #[case(E_DEPRECATED, "Deprecated")]
+  #[case(0, "Unknown error")]
+  fn test_kind(#[case] error_type: u32, #[case] expected: &str) {
+    let error = LastError {
+      error_type: error_type.cast_signed(),
+      message: String::default(),
+      file: String::default(),
+      line: 0,
+    };
+    assert_eq!(error.kind(), expected);
+  }
+
+  #[test]
+  fn test_bailout_display() {
+    let error = LastError {
+      error_type: 1,
+      message: "Uncaught Exception: foo".to_string(),
+      file: "/srv/www/index.php".to_string(),
+      line: 3,
+    };
+    assert_eq!(
+      ExecutePhpError::Bailout(Some(error)).to_string(),
+      "A bailout occurred during the execution: PHP Fatal error: Uncaught Exception: foo in /srv/www/index.php on line 3"
+    );
+    assert_eq!(ExecutePhpError::Bailout(None).to_string(), "A bailout occurred during the execution");
+  }
 }
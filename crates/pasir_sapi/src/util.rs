@@ -23,3 +23,16 @@ macro_rules! free_raw_cstring_mut {
     }
   };
 }
+
+/// Enters a debug span named `$name` with the `tracing` feature, until the returned guard is dropped.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! enter_span {
+  ($name:literal) => {{
+    #[cfg(feature = "tracing")]
+    let span = Some(tracing::debug_span!($name).entered());
+    #[cfg(not(feature = "tracing"))]
+    let span: Option<()> = None;
+    span
+  }};
+}
@@ -0,0 +1,233 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use hyper::Method;
+use hyper::StatusCode;
+use hyper::Uri;
+use hyper::Version;
+use hyper::http::HeaderValue;
+use serde::Serialize;
+
+use crate::util::log_file::LogFile;
+use crate::util::log_file::clf_time;
+use crate::util::log_file::rfc3339_time;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub(crate) enum AccessLogFormat {
+  /// Apache combined log format.
+  #[default]
+  Combined,
+  /// Apache common log format.
+  Common,
+  /// One JSON object per line, with the request id, script, PHP time and route.
+  Json,
+}
+
+/// Access log of the served requests, in one of the `AccessLogFormat`.
+#[derive(Clone, Debug)]
+pub(crate) struct AccessLog {
+  format: AccessLogFormat,
+  file: LogFile,
+}
+
+/// A served request, recorded once its response body has been sent or dropped.
+#[derive(Debug)]
+pub(crate) struct Entry {
+  pub(crate) time: SystemTime,
+  pub(crate) remote_addr: Option<IpAddr>,
+  pub(crate) user: Option<String>,
+  pub(crate) method: Method,
+  pub(crate) uri: Uri,
+  pub(crate) version: Version,
+  pub(crate) status: StatusCode,
+  pub(crate) size: u64,
+  pub(crate) referer: Option<HeaderValue>,
+  pub(crate) user_agent: Option<HeaderValue>,
+  pub(crate) request_id: Option<HeaderValue>,
+  pub(crate) script: Option<String>,
+  pub(crate) php_time: Option<Duration>,
+  pub(crate) duration: Duration,
+  pub(crate) route: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+  time: String,
+  remote_addr: Option<IpAddr>,
+  user: Option<&'a str>,
+  method: &'a str,
+  uri: String,
+  protocol: String,
+  status: u16,
+  size: u64,
+  referer: Option<&'a str>,
+  user_agent: Option<&'a str>,
+  request_id: Option<&'a str>,
+  script: Option<&'a str>,
+  php_time: Option<f64>,
+  duration: f64,
+  route: Option<usize>,
+}
+
+impl AccessLog {
+  pub(crate) fn open(path: PathBuf, format: AccessLogFormat) -> anyhow::Result<Self> {
+    Ok(Self {
+      format,
+      file: LogFile::open(path)?,
+    })
+  }
+
+  #[cfg(test)]
+  pub(crate) fn channel(format: AccessLogFormat) -> (Self, tokio::sync::mpsc::Receiver<String>) {
+    let (file, rx) = LogFile::channel();
+    (Self { format, file }, rx)
+  }
+
+  pub(crate) fn log(&self, entry: &Entry) {
+    self.file.write(entry.format(self.format));
+  }
+}
+
+impl Entry {
+  /// Formats the entry as a line, including the trailing newline.
+  pub(crate) fn format(&self, format: AccessLogFormat) -> String {
+    if format == AccessLogFormat::Json {
+      let mut line = serde_json::to_string(&self.json()).unwrap_or_default();
+      line.push('\n');
+      return line;
+    }
+
+    let mut line = format!(
+      "{} - {} [{}] \"{}\" {} {}",
+      self.remote_addr.map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+      self.user.as_deref().map_or_else(|| "-".to_string(), |user| escape(user.as_bytes())),
+      clf_time(self.time),
+      escape(format!("{} {} {:?}", self.method, self.uri, self.version).as_bytes()),
+      self.status.as_u16(),
+      if self.size == 0 { "-".to_string() } else { self.size.to_string() },
+    );
+    if format == AccessLogFormat::Combined {
+      let header = |value: &Option<HeaderValue>| value.as_ref().map_or_else(|| "-".to_string(), |value| escape(value.as_bytes()));
+      let _ = write!(line, " \"{}\" \"{}\"", header(&self.referer), header(&self.user_agent));
+    }
+    line.push('\n');
+    line
+  }
+
+  fn json(&self) -> JsonEntry<'_> {
+    let header = |value: &'_ Option<HeaderValue>| value.as_ref().and_then(|value| value.to_str().ok());
+    JsonEntry {
+      time: rfc3339_time(self.time),
+      remote_addr: self.remote_addr,
+      user: self.user.as_deref(),
+      method: self.method.as_str(),
+      uri: self.uri.to_string(),
+      protocol: format!("{:?}", self.version),
+      status: self.status.as_u16(),
+      size: self.size,
+      referer: header(&self.referer),
+      user_agent: header(&self.user_agent),
+      request_id: header(&self.request_id),
+      script: self.script.as_deref(),
+      php_time: self.php_time.map(|time| time.as_secs_f64()),
+      duration: self.duration.as_secs_f64(),
+      route: self.route,
+    }
+  }
+}
+
+/// Escapes quotes, backslashes and non-printable bytes the way Apache does in quoted fields.
+fn escape(bytes: &[u8]) -> String {
+  let mut escaped = String::with_capacity(bytes.len());
+  for byte in bytes {
+    match byte {
+      b'"' | b'\\' => {
+        escaped.push('\\');
+        escaped.push(*byte as char);
+      }
+      0x20..=0x7e => escaped.push(*byte as char),
+      _ => {
+        let _ = write!(escaped, "\\x{byte:02x}");
+      }
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use std::time::UNIX_EPOCH;
+
+  use hyper::Method;
+  use hyper::StatusCode;
+  use hyper::Version;
+  use hyper::http::HeaderValue;
+  use rstest::rstest;
+
+  use crate::access_log::AccessLogFormat;
+  use crate::access_log::Entry;
+  use crate::access_log::escape;
+
+  fn entry() -> Entry {
+    Entry {
+      time: UNIX_EPOCH + Duration::from_millis(971_185_336_042),
+      remote_addr: Some("127.0.0.1".parse().unwrap()),
+      user: Some("frank".to_string()),
+      method: Method::GET,
+      uri: "/apache_pb.gif?a=1".parse().unwrap(),
+      version: Version::HTTP_11,
+      status: StatusCode::OK,
+      size: 2326,
+      referer: Some(HeaderValue::from_static("http://www.example.com/start.html")),
+      user_agent: Some(HeaderValue::from_static("Mozilla/4.08 \"en\"")),
+      request_id: Some(HeaderValue::from_static("f81d4fae")),
+      script: Some("/index.php".to_string()),
+      php_time: Some(Duration::from_millis(12)),
+      duration: Duration::from_millis(15),
+      route: Some(2),
+    }
+  }
+
+  #[rstest]
+  #[case(
+    AccessLogFormat::Common,
+    "127.0.0.1 - frank [10/Oct/2000:13:42:16 +0000] \"GET /apache_pb.gif?a=1 HTTP/1.1\" 200 2326\n"
+  )]
+  #[case(
+    AccessLogFormat::Combined,
+    "127.0.0.1 - frank [10/Oct/2000:13:42:16 +0000] \"GET /apache_pb.gif?a=1 HTTP/1.1\" 200 2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"en\\\"\"\n"
+  )]
+  #[case(
+    AccessLogFormat::Json,
+    "{\"time\":\"2000-10-10T13:42:16.042Z\",\"remote_addr\":\"127.0.0.1\",\"user\":\"frank\",\"method\":\"GET\",\"uri\":\"/apache_pb.gif?a=1\",\"protocol\":\"HTTP/1.1\",\"status\":200,\"size\":2326,\"referer\":\"http://www.example.com/start.html\",\"user_agent\":\"Mozilla/4.08 \\\"en\\\"\",\"request_id\":\"f81d4fae\",\"script\":\"/index.php\",\"php_time\":0.012,\"duration\":0.015,\"route\":2}\n"
+  )]
+  fn test_format(#[case] format: AccessLogFormat, #[case] expected: &str) {
+    assert_eq!(entry().format(format), expected);
+  }
+
+  #[test]
+  fn test_format_missing_fields() {
+    let entry = Entry {
+      remote_addr: None,
+      user: None,
+      size: 0,
+      referer: None,
+      user_agent: None,
+      ..entry()
+    };
+    assert_eq!(
+      entry.format(AccessLogFormat::Combined),
+      "- - - [10/Oct/2000:13:42:16 +0000] \"GET /apache_pb.gif?a=1 HTTP/1.1\" 200 - \"-\" \"-\"\n"
+    );
+  }
+
+  #[test]
+  fn test_escape() {
+    assert_eq!(escape(b"foo \"bar\" \\ baz"), "foo \\\"bar\\\" \\\\ baz");
+    assert_eq!(escape(b"\n\xff"), "\\x0a\\xff");
+  }
+}
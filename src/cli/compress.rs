@@ -0,0 +1,200 @@
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tracing::debug;
+use tracing::info;
+
+use crate::cli::Executable;
+use crate::cli::parse_config;
+use crate::cli::parse_root;
+use crate::config::compression::Compression;
+use crate::config::route::Routes;
+use crate::config::static_files::Encoding;
+
+/// Pre-generates the precompressed siblings of the static assets under the document root.
+///
+/// Assets are selected by the MIME types and minimum size of the `[compression]` table, and a
+/// sibling is only kept when it is smaller than the asset.
+#[derive(Clone, Debug, clap::Args)]
+pub struct Compress {
+  #[arg(
+    default_value_os_t = std::env::current_dir().unwrap_or(PathBuf::from(".")),
+    value_parser = parse_root,
+  )]
+  root: PathBuf,
+  #[arg(short, long, help = "Configuration file, relative to the current directory [default: [ROOT]/pasir.toml]", value_parser = parse_config)]
+  config: Option<PathBuf>,
+  #[arg(
+    short,
+    long,
+    help = "Encodings to generate [default: `precompressed` of the `[static]` table]",
+    value_enum,
+    value_delimiter = ','
+  )]
+  encoding: Vec<Encoding>,
+  #[arg(short, long, help = "Regenerate siblings that are newer than their asset")]
+  force: bool,
+}
+
+impl Executable for Compress {
+  async fn execute(self) -> anyhow::Result<()> {
+    let routes = Routes::from_file(self.config.clone().unwrap_or(self.root.join("pasir.toml")))?;
+    let encodings = match self.encoding.is_empty() {
+      true => routes.static_files().precompressed().to_vec(),
+      false => self.encoding.clone(),
+    };
+    let compression = routes.compression();
+
+    let mut count = 0;
+    for path in walk(&self.root)? {
+      count += self.compress_file(&path, &encodings, &compression)?;
+    }
+    info!("Generated {count} precompressed files in {:?}", self.root);
+
+    Ok(())
+  }
+}
+
+impl Compress {
+  fn compress_file(&self, path: &Path, encodings: &[Encoding], compression: &Compression) -> anyhow::Result<usize> {
+    let Some(mime) = mime_guess::from_path(path).first_raw() else {
+      return Ok(0);
+    };
+    let metadata = path.metadata()?;
+    if !compression.allows_type(mime) || metadata.len() < compression.min_size() {
+      return Ok(0);
+    }
+
+    let mut count = 0;
+    let mut asset = None;
+    for encoding in encodings {
+      let sibling = encoding.sibling(path);
+      if !self.force
+        && let Ok(modified) = sibling.metadata().and_then(|sibling| sibling.modified())
+        && modified >= metadata.modified()?
+      {
+        debug!("Skipping up to date {sibling:?}");
+        continue;
+      }
+
+      if asset.is_none() {
+        asset = Some(std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?);
+      }
+      let content = asset.as_deref().unwrap_or_default();
+      let compressed = compress(*encoding, content)?;
+      if compressed.len() >= content.len() {
+        debug!("Skipping {sibling:?}, not smaller than the asset");
+        continue;
+      }
+
+      std::fs::write(&sibling, compressed).with_context(|| format!("Failed to write {sibling:?}"))?;
+      debug!("Generated {sibling:?}");
+      count += 1;
+    }
+
+    Ok(count)
+  }
+}
+
+/// Lists the files under `root`, skipping hidden files and directories.
+fn walk(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+  let mut files = Vec::new();
+  let mut directories = vec![root.to_path_buf()];
+  while let Some(directory) = directories.pop() {
+    for entry in std::fs::read_dir(&directory).with_context(|| format!("Failed to read directory {directory:?}"))? {
+      let entry = entry?;
+      if entry.file_name().to_string_lossy().starts_with('.') {
+        continue;
+      }
+      let file_type = entry.file_type()?;
+      if file_type.is_dir() {
+        directories.push(entry.path());
+      } else if file_type.is_file() {
+        files.push(entry.path());
+      }
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+fn compress(encoding: Encoding, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+  let compressed = match encoding {
+    Encoding::Gzip => {
+      let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+      encoder.write_all(content)?;
+      encoder.finish()?
+    }
+    Encoding::Deflate => {
+      let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+      encoder.write_all(content)?;
+      encoder.finish()?
+    }
+    Encoding::Br => {
+      let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+      encoder.write_all(content)?;
+      encoder.into_inner()
+    }
+    Encoding::Zstd => zstd::encode_all(content, 19)?,
+  };
+  Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+  use std::path::PathBuf;
+
+  use rstest::rstest;
+
+  use crate::cli::Executable;
+  use crate::cli::compress::Compress;
+  use crate::cli::compress::compress;
+  use crate::config::static_files::Encoding;
+
+  #[rstest]
+  #[case(Encoding::Gzip)]
+  #[case(Encoding::Deflate)]
+  #[case(Encoding::Br)]
+  #[case(Encoding::Zstd)]
+  fn test_compress(#[case] encoding: Encoding) {
+    let content = "body { color: red; }\n".repeat(64);
+    let compressed = compress(encoding, content.as_bytes()).unwrap();
+    assert!(compressed.len() < content.len());
+
+    let mut decompressed = String::new();
+    match encoding {
+      Encoding::Gzip => flate2::read::GzDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed),
+      Encoding::Deflate => flate2::read::ZlibDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed),
+      Encoding::Br => brotli::Decompressor::new(compressed.as_slice(), 4096).read_to_string(&mut decompressed),
+      Encoding::Zstd => zstd::Decoder::new(compressed.as_slice()).unwrap().read_to_string(&mut decompressed),
+    }
+    .unwrap();
+    assert_eq!(decompressed, content);
+  }
+
+  #[tokio::test]
+  async fn test_execute() {
+    let root = std::env::temp_dir().join(format!("pasir-compress-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("assets/app.css"), "body { color: red; }\n".repeat(64)).unwrap();
+    std::fs::write(root.join("assets/tiny.css"), "a{}").unwrap();
+    std::fs::write(root.join("index.php"), "<?php echo 'foo';\n".repeat(64)).unwrap();
+
+    let compress = Compress {
+      root: root.clone(),
+      config: Some(PathBuf::from("tests/fixtures/foo.toml")),
+      encoding: vec![Encoding::Gzip, Encoding::Br],
+      force: false,
+    };
+    compress.execute().await.unwrap();
+
+    assert!(root.join("assets/app.css.gz").is_file());
+    assert!(root.join("assets/app.css.br").is_file());
+    assert!(!root.join("assets/tiny.css.gz").exists());
+    assert!(!root.join("index.php.gz").exists());
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
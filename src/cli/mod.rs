@@ -1,8 +1,12 @@
+mod compress;
 mod info;
 mod module;
 pub mod serve;
 
+use std::net::SocketAddr;
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap_verbosity_flag::InfoLevel;
 use clap_verbosity_flag::Verbosity;
@@ -16,9 +20,12 @@ use pasir_sys::ZEND_RESULT_CODE_SUCCESS;
 #[cfg(php83)]
 use tokio::runtime::Handle;
 
+use crate::access_log::AccessLogFormat;
+use crate::cli::compress::Compress;
 use crate::cli::info::Info;
 use crate::cli::module::Module;
 use crate::cli::serve::Serve;
+use crate::config::parse_duration;
 use crate::sapi::Sapi;
 
 pub trait Executable {
@@ -40,8 +47,17 @@ pub trait Executable {
 }
 
 #[derive(Clone, Debug, clap::Parser)]
-#[command(version, long_version = long_version(), about, author)]
+#[command(
+  version,
+  long_version = long_version(),
+  about,
+  author,
+  args_conflicts_with_subcommands = true,
+  subcommand_negates_reqs = true
+)]
 pub struct Cli {
+  #[command(subcommand)]
+  command: Option<Command>,
   #[arg(
     default_value_os_t = std::env::current_dir().unwrap_or(PathBuf::from(".")),
     value_parser = parse_root,
@@ -53,24 +69,110 @@ pub struct Cli {
   port: Option<u16>,
   #[arg(short, long, help = "Configuration file for routes, relative to the current directory [default: [ROOT]/pasir.toml]", value_parser = parse_config)]
   config: Option<PathBuf>,
+  #[arg(
+    long,
+    env = "PASIR_METRICS_LISTEN",
+    help = "Address to expose Prometheus metrics on, e.g. 127.0.0.1:9090"
+  )]
+  metrics_listen: Option<SocketAddr>,
+  #[arg(
+    long,
+    env = "PASIR_ACCESS_LOG",
+    help = "Write an access log to the file, or to stdout for '-'",
+    value_name = "FILE"
+  )]
+  access_log: Option<PathBuf>,
+  #[arg(
+    long,
+    env = "PASIR_ACCESS_LOG_FORMAT",
+    value_enum,
+    default_value_t,
+    help = "Format of the access log"
+  )]
+  access_log_format: AccessLogFormat,
+  #[arg(
+    long,
+    env = "PASIR_SLOWLOG",
+    help = "Log the PHP backtrace of slow requests to the file, or to stdout for '-'",
+    value_name = "FILE"
+  )]
+  slowlog: Option<PathBuf>,
+  #[arg(
+    long,
+    env = "PASIR_SLOWLOG_TIMEOUT",
+    help = "Execution time after which a request is logged to the slowlog",
+    value_name = "DURATION",
+    default_value = "5s",
+    value_parser = parse_duration
+  )]
+  slowlog_timeout: Duration,
   #[arg(short, long, help = "Define INI entry foo with value 'bar'", value_name = "foo[=bar]", value_parser = parse_define)]
   define: Vec<String>,
   #[arg(short, long, help = "PHP information and configuration", conflicts_with = "modules")]
   info: bool,
   #[arg(short, long, help = "Show compiled in modules", conflicts_with = "info")]
   modules: bool,
+  #[arg(long, env = "PASIR_LOG_FORMAT", value_enum, default_value_t, help = "Format of the diagnostic log")]
+  log_format: LogFormat,
+  #[arg(
+    long,
+    env = "PASIR_LOG_FILE",
+    help = "Write the diagnostic log to the file instead of stderr",
+    value_name = "FILE"
+  )]
+  log_file: Option<PathBuf>,
+  #[arg(
+    long,
+    env = "PASIR_OTLP_ENDPOINT",
+    help = "Export spans to an OTLP collector over gRPC, e.g. http://127.0.0.1:4317",
+    value_name = "URL"
+  )]
+  otlp_endpoint: Option<String>,
   #[command(flatten)]
   verbosity: Verbosity<InfoLevel>,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub(crate) enum LogFormat {
+  /// One JSON object per line, with the event and span fields.
+  Json,
+  /// Single line human readable messages.
+  #[default]
+  Compact,
+  /// Multi-line human readable messages.
+  Pretty,
+}
+
+#[derive(Clone, Debug, clap::Subcommand)]
+enum Command {
+  /// Pre-generate precompressed static assets
+  Compress(Compress),
+}
+
 impl Cli {
   pub(crate) fn verbosity(&self) -> Verbosity<InfoLevel> {
     self.verbosity
   }
+
+  pub(crate) fn log_format(&self) -> LogFormat {
+    self.log_format
+  }
+
+  pub(crate) fn log_file(&self) -> Option<&Path> {
+    self.log_file.as_deref()
+  }
+
+  pub(crate) fn otlp_endpoint(&self) -> Option<&str> {
+    self.otlp_endpoint.as_deref()
+  }
 }
 
 impl Executable for Cli {
   async fn execute(self) -> anyhow::Result<()> {
+    if let Some(Command::Compress(compress)) = self.command {
+      return compress.execute().await;
+    }
+
     #[cfg(not(php83))]
     unsafe {
       ext_php_rs::embed::ext_php_rs_sapi_startup()
@@ -114,7 +216,10 @@ impl Executable for Cli {
       Module::default().execute().await
     } else {
       let config = self.config.unwrap_or(self.root.join("pasir.toml"));
-      Serve::new(self.address, self.port.expect("PORT argument were not provided"), self.root, config)
+      let port = self.port.expect("PORT argument were not provided");
+      let access_log = self.access_log.map(|path| (path, self.access_log_format));
+      let slowlog = self.slowlog.map(|path| (path, self.slowlog_timeout));
+      Serve::new(self.address, port, self.root, config, self.metrics_listen, access_log, slowlog)
         .execute()
         .await
     };
@@ -163,6 +268,7 @@ fn parse_define(arg: &str) -> anyhow::Result<String> {
 mod tests {
   use std::net::Ipv4Addr;
   use std::path::PathBuf;
+  use std::time::Duration;
 
   use clap_verbosity_flag::Verbosity;
   use clap_verbosity_flag::VerbosityFilter;
@@ -178,13 +284,22 @@ mod tests {
     #[test]
     fn test_config(root: PathBuf, address: Ipv4Addr, port: u16, config: PathBuf, verbose in 0..3u8, quiet in 0..=3u8) {
       let cli = Cli {
+        command: None,
         root: root.clone(),
         address: address.to_string(),
         port: Some(port),
         config: Some(config),
+        metrics_listen: None,
+        access_log: None,
+        access_log_format: Default::default(),
+        slowlog: None,
+        slowlog_timeout: Duration::from_secs(5),
         define: vec![],
         info: false,
         modules: false,
+        log_format: Default::default(),
+        log_file: None,
+        otlp_endpoint: None,
         verbosity: Verbosity::new(verbose, quiet),
       };
 
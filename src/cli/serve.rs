@@ -5,8 +5,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
-#[cfg(not(php_zend_max_execution_timers))]
-use ext_php_rs::zend::ExecutorGlobals;
+use hyper::body::Incoming;
 use hyper::header::SERVER;
 use hyper::http::HeaderValue;
 use hyper_util::rt::TokioExecutor;
@@ -16,21 +15,34 @@ use hyper_util::server::graceful::GracefulShutdown;
 use hyper_util::service::TowerToHyperService;
 use tokio::net::TcpListener;
 use tokio::signal::unix::SignalKind;
+use tokio::time::Instant;
+use tower::Layer;
 use tower::ServiceBuilder;
-#[cfg(not(php_zend_max_execution_timers))]
-use tower::timeout::TimeoutLayer;
 use tower_http::ServiceBuilderExt;
 use tower_http::request_id::MakeRequestUuid;
-use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
+use tracing::Instrument;
 use tracing::debug;
+use tracing::debug_span;
 use tracing::error;
 use tracing::info;
 
+use crate::access_log::AccessLog;
+use crate::access_log::AccessLogFormat;
 use crate::cli::Executable;
 use crate::config::route::Routes;
+use crate::metrics::METRICS;
+use crate::service::AccessLogLayer;
+use crate::service::CacheLayer;
+use crate::service::ErrorPageLayer;
+use crate::service::EtagLayer;
+use crate::service::MetricsLayer;
 use crate::service::PhpService;
+use crate::service::ProxyService;
 use crate::service::RouterService;
+use crate::slowlog::SLOWLOG;
+use crate::slowlog::Slowlog;
+use crate::util::log_file::LogFile;
 
 #[derive(Debug)]
 pub struct Stream {
@@ -68,55 +80,88 @@ pub struct Serve {
   port: u16,
   root: PathBuf,
   config: PathBuf,
+  metrics_listen: Option<SocketAddr>,
+  access_log: Option<(PathBuf, AccessLogFormat)>,
+  slowlog: Option<(PathBuf, Duration)>,
 }
 
 impl Serve {
-  pub fn new(address: String, port: u16, root: PathBuf, config: PathBuf) -> Self {
+  pub(crate) fn new(
+    address: String,
+    port: u16,
+    root: PathBuf,
+    config: PathBuf,
+    metrics_listen: Option<SocketAddr>,
+    access_log: Option<(PathBuf, AccessLogFormat)>,
+    slowlog: Option<(PathBuf, Duration)>,
+  ) -> Self {
     Self {
       address,
       port,
       root,
       config,
+      metrics_listen,
+      access_log,
+      slowlog,
     }
   }
 
   async fn serve(self) -> anyhow::Result<()> {
     info!("Pasir running on [http://{}:{}]", self.address, self.port);
+    crate::status::start();
 
     let routes = Arc::new(Routes::from_file(self.config)?);
     let listener = TcpListener::bind((self.address, self.port)).await?;
+    if let Some(metrics_listen) = self.metrics_listen {
+      let metrics_listener = TcpListener::bind(metrics_listen).await?;
+      info!("Metrics available on [http://{metrics_listen}/metrics]");
+      tokio::spawn(crate::metrics::serve(metrics_listener));
+    }
+    if let Some((path, timeout)) = self.slowlog {
+      let slowlog = Slowlog::new(timeout, LogFile::open(path)?);
+      crate::executions::install_interrupt_function();
+      tokio::spawn(SLOWLOG.get_or_init(|| slowlog).watch());
+    }
+    let access_log_layer = AccessLogLayer::new(self.access_log.map(|(path, format)| AccessLog::open(path, format)).transpose()?);
     let http = Builder::new(TokioExecutor::new());
     let graceful = GracefulShutdown::new();
     let mut sigterm = tokio::signal::unix::signal(SignalKind::terminate())?;
     let server = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+    let proxy_service = ProxyService::default();
+    let cache_layer = CacheLayer::new(routes.cache().cloned());
+    // Once shutdown starts, connections are still accepted until the drain period elapses.
+    let drain = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(drain);
+    let mut draining = false;
 
     loop {
       tokio::select! {
         Ok((stream, socket)) = listener.accept() => {
           let php_service = PhpService::default();
-          let serve_dir = ServeDir::new(self.root.clone())
-              .call_fallback_on_method_not_allowed(true)
-              .append_index_html_on_directories(false)
-              .precompressed_gzip();
+          let serve_dir = crate::service::serve_dir(&self.root, routes.static_files());
 
           let tower_service = ServiceBuilder::new()
             .add_extension(Arc::new(self.root.clone()))
             .add_extension(routes.clone())
             .add_extension(Arc::new(Stream::new(stream.local_addr()?, socket)))
             .set_x_request_id(MakeRequestUuid)
-            .layer(TraceLayer::new_for_http().on_request(()))
+            .layer(TraceLayer::new_for_http().make_span_with(crate::otel::make_span::<Incoming>).on_request(()))
+            .layer(MetricsLayer)
+            .layer(access_log_layer.clone())
             .propagate_x_request_id()
-            .insert_response_header_if_not_present(SERVER, HeaderValue::from_static(server));
+            .insert_response_header_if_not_present(SERVER, HeaderValue::from_static(server))
+            .layer(routes.compression().layer())
+            .layer(EtagLayer)
+            .layer(ErrorPageLayer::new(php_service.clone()));
 
-          #[cfg(not(php_zend_max_execution_timers))]
-          let tower_service = tower_service.map_result(crate::service::map_result)
-            .layer(TimeoutLayer::new(Duration::from_secs(ExecutorGlobals::get().timeout_seconds.cast_unsigned())));
-
-          let tower_service = tower_service.service(RouterService::new(serve_dir, php_service));
+          let tower_service = tower_service.service(RouterService::new(serve_dir, cache_layer.layer(php_service), proxy_service.clone()));
 
           let connection = http.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(tower_service));
           let future = graceful.watch(connection.into_owned());
+          let connection_guard = METRICS.connection();
+          let connection_span = debug_span!("connection", client.address = %socket);
           tokio::spawn(async move {
+            let _connection_guard = connection_guard;
             if let Err(err) = future.await {
               if let Some(hyper_error) = err.downcast_ref::<hyper::Error>() && hyper_error.is_incomplete_message() {
                 debug!("Error serving connection: {err}");
@@ -125,15 +170,22 @@ impl Serve {
                 error!("Error serving connection: {err}");
               }
             }
-          });
+          }.instrument(connection_span));
         },
 
-        _ = tokio::signal::ctrl_c() => {
-          drop(listener);
-          info!("Starting graceful shutdown");
-          break;
+        _ = tokio::signal::ctrl_c(), if !draining => {
+          draining = true;
+          crate::service::health::drain();
+          drain.as_mut().reset(Instant::now() + routes.health().drain_period());
+          info!("Draining for {:?}", routes.health().drain_period());
+        }
+        _ = sigterm.recv(), if !draining => {
+          draining = true;
+          crate::service::health::drain();
+          drain.as_mut().reset(Instant::now() + routes.health().drain_period());
+          info!("Draining for {:?}", routes.health().drain_period());
         }
-        _ = sigterm.recv() => {
+        _ = &mut drain, if draining => {
           drop(listener);
           info!("Starting graceful shutdown");
           break;
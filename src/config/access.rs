@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use headers::Authorization;
+use headers::HeaderMapExt;
+use headers::authorization::Basic;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::WWW_AUTHENTICATE;
+use hyper::http::HeaderValue;
+use ipnet::IpNet;
+use md5::Digest;
+use md5::Md5;
+use serde::Deserialize;
+use tracing::warn;
+
+/// How long credentials verified against a bcrypt hash are accepted without verifying them again.
+const VERIFIED_TTL: Duration = Duration::from_secs(60);
+
+/// List of networks in CIDR notation, a bare address matching only itself.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub(crate) struct CidrList(Vec<IpNet>);
+
+impl CidrList {
+  pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+    let addr = addr.to_canonical();
+    self.0.iter().any(|net| net.contains(&addr))
+  }
+}
+
+impl TryFrom<Vec<String>> for CidrList {
+  type Error = anyhow::Error;
+
+  fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+    value
+      .iter()
+      .map(|value| match value.parse::<IpNet>() {
+        Ok(net) => Ok(net),
+        Err(_) => value
+          .parse::<IpAddr>()
+          .map(IpNet::from)
+          .with_context(|| format!("Invalid CIDR or IP address: {value}")),
+      })
+      .collect::<anyhow::Result<_>>()
+      .map(Self)
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Auth {
+  Basic(BasicAuth),
+}
+
+impl Auth {
+  pub(crate) fn load(&mut self, base: &Path) -> anyhow::Result<()> {
+    match self {
+      Auth::Basic(basic) => basic.load(base),
+    }
+  }
+
+  /// Returns the authenticated user, or the access denial to answer the request with.
+  ///
+  /// Credentials matching a bcrypt hash are only accepted once verified by their `CredentialCheck`.
+  pub(crate) fn authenticate<B>(&self, request: &Request<B>) -> Result<String, AccessDenied> {
+    match self {
+      Auth::Basic(basic) => basic.authenticate(request),
+    }
+  }
+
+  /// Returns the slow verification the credentials of the request need before `authenticate`, if any.
+  pub(crate) fn credential_check<B>(&self, request: &Request<B>) -> Option<CredentialCheck> {
+    match self {
+      Auth::Basic(basic) => basic.credential_check(request),
+    }
+  }
+}
+
+/// Verification of credentials against a bcrypt hash, too slow to run on the runtime workers.
+pub(crate) struct CredentialCheck {
+  auth: BasicAuth,
+  user: String,
+  password: String,
+}
+
+impl CredentialCheck {
+  /// Verifies the credentials, remembering them for `VERIFIED_TTL` when they match.
+  pub(crate) fn run(self) {
+    let Some(hash) = self.auth.users.get(&self.user) else {
+      return;
+    };
+    if pwhash::bcrypt::verify(&self.password, hash) {
+      let mut verified = self.auth.verified.lock().unwrap();
+      let now = Instant::now();
+      verified.retain(|_, expires| *expires > now);
+      verified.insert(credentials_digest(&self.user, &self.password), now + VERIFIED_TTL);
+    }
+  }
+}
+
+/// HTTP basic authentication against an htpasswd file of bcrypt or apr1 hashes.
+///
+/// The file path is relative to the directory of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct BasicAuth {
+  realm: String,
+  htpasswd: PathBuf,
+  #[serde(skip)]
+  users: Arc<HashMap<String, String>>,
+  /// Digests of the credentials that matched a bcrypt hash, with the time they expire.
+  #[serde(skip)]
+  verified: Arc<Mutex<HashMap<[u8; 16], Instant>>>,
+}
+
+impl BasicAuth {
+  fn load(&mut self, base: &Path) -> anyhow::Result<()> {
+    let path = base.join(&self.htpasswd);
+    let content = std::fs::read_to_string(&path).with_context(|| format!("Failed to read htpasswd file: {path:?}"))?;
+    let mut users = HashMap::new();
+    for line in content.lines().map(str::trim) {
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      match line.split_once(':') {
+        Some((user, hash)) if is_supported_hash(hash) => {
+          users.insert(user.to_string(), hash.to_string());
+        }
+        _ => warn!("Ignoring unsupported htpasswd entry in {path:?}: {line}"),
+      }
+    }
+    self.users = Arc::new(users);
+    Ok(())
+  }
+
+  fn authenticate<B>(&self, request: &Request<B>) -> Result<String, AccessDenied> {
+    let credentials = request.headers().typed_get::<Authorization<Basic>>();
+    match credentials {
+      Some(credentials) if self.verify(credentials.username(), credentials.password()) => Ok(credentials.username().to_string()),
+      _ => Err(AccessDenied::Unauthorized(self.challenge())),
+    }
+  }
+
+  fn credential_check<B>(&self, request: &Request<B>) -> Option<CredentialCheck> {
+    let credentials = request.headers().typed_get::<Authorization<Basic>>()?;
+    let (user, password) = (credentials.username(), credentials.password());
+    let hash = self.users.get(user)?;
+    if hash.starts_with("$apr1$") || self.is_verified(user, password) {
+      return None;
+    }
+    Some(CredentialCheck {
+      auth: self.clone(),
+      user: user.to_string(),
+      password: password.to_string(),
+    })
+  }
+
+  /// Checks apr1 hashes, cheap enough to compute here, and bcrypt ones against the verified credentials.
+  fn verify(&self, user: &str, password: &str) -> bool {
+    let Some(hash) = self.users.get(user) else {
+      return false;
+    };
+    match hash.strip_prefix("$apr1$") {
+      Some(rest) => {
+        let salt = rest.split_once('$').map_or(rest, |(salt, _)| salt);
+        constant_time_eq(apr1(password.as_bytes(), salt.as_bytes()).as_bytes(), hash.as_bytes())
+      }
+      None => self.is_verified(user, password),
+    }
+  }
+
+  fn is_verified(&self, user: &str, password: &str) -> bool {
+    let verified = self.verified.lock().unwrap();
+    verified
+      .get(&credentials_digest(user, password))
+      .is_some_and(|expires| *expires > Instant::now())
+  }
+
+  fn challenge(&self) -> HeaderValue {
+    let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+    HeaderValue::from_str(&format!("Basic realm=\"{realm}\", charset=\"UTF-8\"")).unwrap_or(HeaderValue::from_static("Basic"))
+  }
+}
+
+/// Reason a request is refused by the access rules of a route.
+#[derive(Debug, PartialEq)]
+pub(crate) enum AccessDenied {
+  Forbidden,
+  Unauthorized(HeaderValue),
+}
+
+impl AccessDenied {
+  pub(crate) fn into_response<B: Default>(self) -> Response<B> {
+    let mut response = Response::<B>::default();
+    match self {
+      AccessDenied::Forbidden => *response.status_mut() = StatusCode::FORBIDDEN,
+      AccessDenied::Unauthorized(challenge) => {
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+      }
+    }
+    response
+  }
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+  ["$apr1$", "$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+/// Keeps the verified passwords out of memory, and of the `Debug` output of the configuration.
+fn credentials_digest(user: &str, password: &str) -> [u8; 16] {
+  Md5::new()
+    .chain_update(user)
+    .chain_update(":")
+    .chain_update(password)
+    .finalize()
+    .into()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Apache's variant of the MD5 based crypt, as produced by `htpasswd -m`.
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+  const MAGIC: &[u8] = b"$apr1$";
+  const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+  let salt = &salt[..salt.len().min(8)];
+  let alternate = Md5::new()
+    .chain_update(password)
+    .chain_update(salt)
+    .chain_update(password)
+    .finalize();
+
+  let mut context = Md5::new().chain_update(password).chain_update(MAGIC).chain_update(salt);
+  for chunk in (0..password.len()).step_by(16) {
+    context.update(&alternate[..(password.len() - chunk).min(16)]);
+  }
+  let mut length = password.len();
+  while length > 0 {
+    match length & 1 {
+      1 => context.update([0]),
+      _ => context.update(&password[..1]),
+    }
+    length >>= 1;
+  }
+
+  let mut digest = context.finalize();
+  for round in 0..1000 {
+    let mut context = Md5::new();
+    match round & 1 {
+      1 => context.update(password),
+      _ => context.update(digest),
+    }
+    if round % 3 != 0 {
+      context.update(salt);
+    }
+    if round % 7 != 0 {
+      context.update(password);
+    }
+    match round & 1 {
+      1 => context.update(digest),
+      _ => context.update(password),
+    }
+    digest = context.finalize();
+  }
+
+  let mut hash = String::from_utf8_lossy(MAGIC).into_owned();
+  hash.push_str(&String::from_utf8_lossy(salt));
+  hash.push('$');
+  let mut encode = |value: u32, length: usize| {
+    for shift in 0..length {
+      hash.push(ITOA64[((value >> (shift * 6)) & 0x3f) as usize] as char);
+    }
+  };
+  for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+    encode((u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]), 4);
+  }
+  encode(u32::from(digest[11]), 2);
+  hash
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+  use std::path::Path;
+
+  use hyper::Request;
+  use hyper::StatusCode;
+  use hyper::header::AUTHORIZATION;
+  use hyper::header::WWW_AUTHENTICATE;
+  use rstest::rstest;
+
+  use crate::config::access::AccessDenied;
+  use crate::config::access::Auth;
+  use crate::config::access::CidrList;
+  use crate::config::access::apr1;
+
+  #[rstest]
+  #[case("10.1.2.3", true)]
+  #[case("192.168.0.1", true)]
+  #[case("192.168.0.2", false)]
+  #[case("::ffff:10.0.0.1", true)]
+  #[case("fd00::1", true)]
+  #[case("2001:db8::1", false)]
+  fn test_cidr_list(#[case] addr: IpAddr, #[case] expected: bool) {
+    let list = CidrList::try_from(vec!["10.0.0.0/8".to_string(), "192.168.0.1".to_string(), "fd00::/8".to_string()]).unwrap();
+    assert_eq!(list.contains(addr), expected);
+  }
+
+  #[test]
+  fn test_cidr_list_invalid() {
+    assert!(CidrList::try_from(vec!["10.0.0.0/33".to_string()]).is_err());
+    assert!(CidrList::try_from(vec!["localhost".to_string()]).is_err());
+  }
+
+  #[test]
+  fn test_apr1() {
+    assert_eq!(apr1(b"secret", b"xxxxxxxx"), "$apr1$xxxxxxxx$/mULyOsdWlXlIt5U99q7h1");
+  }
+
+  #[rstest]
+  #[case(None, None)]
+  #[case(Some("Basic YWxpY2U6c2VjcmV0"), Some("alice"))]
+  #[case(Some("Basic Ym9iOnBhc3N3b3Jk"), Some("bob"))]
+  #[case(Some("Basic YWxpY2U6cGFzc3dvcmQ="), None)]
+  #[case(Some("Basic Y2Fyb2w6c2VjcmV0"), None)]
+  fn test_basic_auth(#[case] authorization: Option<&str>, #[case] expected: Option<&str>) {
+    let mut auth: Auth = toml::from_str::<toml::Value>(r#"basic = { realm = "Admin \"area\"", htpasswd = "htpasswd" }"#)
+      .unwrap()
+      .try_into()
+      .unwrap();
+    auth.load(Path::new("tests/fixtures")).unwrap();
+
+    let mut builder = Request::builder();
+    if let Some(authorization) = authorization {
+      builder = builder.header(AUTHORIZATION, authorization);
+    }
+    let request = builder.body(String::default()).unwrap();
+    if let Some(check) = auth.credential_check(&request) {
+      check.run();
+    }
+
+    match (auth.authenticate(&request), expected) {
+      (Ok(user), Some(expected)) => assert_eq!(user, expected),
+      (Err(denied), None) => {
+        let response = denied.into_response::<String>();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], r#"Basic realm="Admin \"area\"", charset="UTF-8""#);
+      }
+      (result, _) => panic!("Unexpected authentication result: {result:?}"),
+    }
+  }
+
+  #[test]
+  fn test_credential_check() {
+    let mut auth: Auth = toml::from_str::<toml::Value>(r#"basic = { realm = "Admin", htpasswd = "htpasswd" }"#)
+      .unwrap()
+      .try_into()
+      .unwrap();
+    auth.load(Path::new("tests/fixtures")).unwrap();
+    let request = |authorization: &str| {
+      Request::builder()
+        .header(AUTHORIZATION, authorization)
+        .body(String::default())
+        .unwrap()
+    };
+
+    // apr1 hashes are checked inline.
+    assert!(auth.credential_check(&request("Basic YWxpY2U6c2VjcmV0")).is_none());
+
+    let bob = request("Basic Ym9iOnBhc3N3b3Jk");
+    assert!(auth.authenticate(&bob).is_err());
+    auth.credential_check(&bob).unwrap().run();
+    assert_eq!(auth.authenticate(&bob).unwrap(), "bob");
+    assert!(auth.credential_check(&bob).is_none());
+
+    let wrong = request("Basic Ym9iOnNlY3JldA==");
+    auth.credential_check(&wrong).unwrap().run();
+    assert!(auth.authenticate(&wrong).is_err());
+    assert!(auth.credential_check(&wrong).is_some());
+  }
+
+  #[test]
+  fn test_access_denied_forbidden() {
+    let response = AccessDenied::Forbidden.into_response::<String>();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+  }
+}
@@ -0,0 +1,280 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use headers::Cookie;
+use headers::HeaderMapExt;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::AUTHORIZATION;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::SET_COOKIE;
+use hyper::header::VARY;
+use serde::Deserialize;
+
+use crate::config::access::CidrList;
+use crate::config::deserialize_duration;
+use crate::config::route::request_host;
+
+/// Statuses a shared cache may store when the response is explicitly marked cacheable.
+const CACHEABLE_STATUSES: [StatusCode; 6] = [
+  StatusCode::OK,
+  StatusCode::NON_AUTHORITATIVE_INFORMATION,
+  StatusCode::MOVED_PERMANENTLY,
+  StatusCode::PERMANENT_REDIRECT,
+  StatusCode::NOT_FOUND,
+  StatusCode::GONE,
+];
+
+/// In-process microcache of PHP responses, configured by the top-level `[cache]` table.
+///
+/// Only `GET` and `HEAD` requests without credentials are cached, unless they carry one of the
+/// `bypass_cookies`, where a trailing `*` matches any suffix. Only responses PHP marks with
+/// `Cache-Control: public, s-maxage=<seconds>` are stored.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Cache {
+  #[serde(default = "default_max_size")]
+  max_size: u64,
+  #[serde(default)]
+  vary_headers: Vec<String>,
+  #[serde(default)]
+  vary_cookies: Vec<String>,
+  #[serde(default)]
+  bypass_cookies: Vec<String>,
+  #[serde(default, deserialize_with = "deserialize_duration")]
+  stale_while_revalidate: Duration,
+  #[serde(default)]
+  purge_from: CidrList,
+}
+
+fn default_max_size() -> u64 {
+  64 * 1024 * 1024
+}
+
+/// Identifies a cached response by the request and the values of its varying headers and cookies.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct CacheKey {
+  method: Method,
+  host: String,
+  path: String,
+  query: Option<String>,
+  variants: Vec<Option<String>>,
+}
+
+impl CacheKey {
+  /// Whether the key is purged by a `PURGE` of `path` on `host`, a trailing `*` matching any suffix.
+  pub(crate) fn matches_purge(&self, host: &str, path: &str) -> bool {
+    self.host == host
+      && match path.strip_suffix('*') {
+        Some(prefix) => self.path.starts_with(prefix),
+        None => self.path == path,
+      }
+  }
+
+  /// Approximate memory used by the key.
+  pub(crate) fn size(&self) -> usize {
+    self.host.len() + self.path.len() + self.query.as_ref().map_or(0, String::len)
+  }
+}
+
+/// Freshness of a cached response, from `s-maxage` and `stale-while-revalidate`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Lifetimes {
+  pub(crate) fresh: Duration,
+  pub(crate) stale: Duration,
+}
+
+impl Cache {
+  pub(crate) fn max_size(&self) -> u64 {
+    self.max_size
+  }
+
+  pub(crate) fn allows_purge(&self, addr: IpAddr) -> bool {
+    self.purge_from.contains(addr)
+  }
+
+  /// Key of a cacheable request, or `None` when the request bypasses the cache.
+  pub(crate) fn key<B>(&self, request: &Request<B>) -> Option<CacheKey> {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) || request.headers().contains_key(AUTHORIZATION) {
+      return None;
+    }
+    let cookie = request.headers().typed_get::<Cookie>();
+    let bypass = cookie.as_ref().is_some_and(|cookie| {
+      cookie.iter().any(|(name, _)| {
+        self.bypass_cookies.iter().any(|pattern| match pattern.strip_suffix('*') {
+          Some(prefix) => name.starts_with(prefix),
+          None => name == pattern,
+        })
+      })
+    });
+    if bypass {
+      return None;
+    }
+
+    let headers = self.vary_headers.iter().map(|name| {
+      let values = request
+        .headers()
+        .get_all(name.as_str())
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>();
+      (!values.is_empty()).then(|| values.join(", "))
+    });
+    let cookies = self
+      .vary_cookies
+      .iter()
+      .map(|name| cookie.as_ref().and_then(|cookie| cookie.get(name)).map(str::to_string));
+    Some(CacheKey {
+      method: request.method().clone(),
+      host: request_host(request).unwrap_or_default(),
+      path: request.uri().path().to_string(),
+      query: request.uri().query().map(str::to_string),
+      variants: headers.chain(cookies).collect(),
+    })
+  }
+
+  /// Lifetimes of a response PHP marked as publicly cacheable, or `None` when it must not be stored.
+  pub(crate) fn lifetimes<B>(&self, response: &Response<B>) -> Option<Lifetimes> {
+    if !CACHEABLE_STATUSES.contains(&response.status()) || response.headers().contains_key(SET_COOKIE) || !self.covers_vary(response) {
+      return None;
+    }
+
+    let (mut public, mut s_maxage, mut stale) = (false, None, None);
+    let directives = response
+      .headers()
+      .get_all(CACHE_CONTROL)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','));
+    for directive in directives {
+      let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+      let seconds = || value.trim().trim_matches('"').parse::<u64>().ok().map(Duration::from_secs);
+      match name.trim().to_ascii_lowercase().as_str() {
+        "public" => public = true,
+        "s-maxage" => s_maxage = seconds(),
+        "stale-while-revalidate" => stale = seconds(),
+        "private" | "no-store" | "no-cache" => return None,
+        _ => {}
+      }
+    }
+
+    match s_maxage {
+      Some(fresh) if public && !fresh.is_zero() => Some(Lifetimes {
+        fresh,
+        stale: stale.unwrap_or(self.stale_while_revalidate),
+      }),
+      _ => None,
+    }
+  }
+
+  /// Whether the cache key covers every request header the response varies on. `Accept-Encoding` is
+  /// negotiated after the cache, and `Cookie` is covered by `vary_cookies`.
+  fn covers_vary<B>(&self, response: &Response<B>) -> bool {
+    response
+      .headers()
+      .get_all(VARY)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .map(|name| name.trim().to_ascii_lowercase())
+      .all(|name| match name.as_str() {
+        "*" => false,
+        "accept-encoding" => true,
+        "cookie" => !self.vary_cookies.is_empty(),
+        name => self.vary_headers.iter().any(|header| header.eq_ignore_ascii_case(name)),
+      })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use rstest::rstest;
+
+  use crate::config::cache::Cache;
+  use crate::config::cache::Lifetimes;
+
+  fn cache() -> Cache {
+    toml::from_str(
+      r#"
+      vary_headers = ["Accept-Language"]
+      vary_cookies = ["currency"]
+      bypass_cookies = ["PHPSESSID", "wordpress_logged_in_*"]
+      stale_while_revalidate = "10s"
+      "#,
+    )
+    .unwrap()
+  }
+
+  fn request(headers: &[(&str, &str)]) -> Request<()> {
+    let mut builder = Request::builder().uri("http://example.com/foo?bar=1");
+    for (name, value) in headers {
+      builder = builder.header(*name, *value);
+    }
+    builder.body(()).unwrap()
+  }
+
+  #[test]
+  fn test_key() {
+    let cache = cache();
+    let key = cache.key(&request(&[])).unwrap();
+    assert_eq!(cache.key(&request(&[("Cookie", "theme=dark")])), Some(key.clone()));
+    assert_ne!(cache.key(&request(&[("Accept-Language", "fr")])), Some(key.clone()));
+    assert_ne!(cache.key(&request(&[("Cookie", "currency=EUR")])), Some(key.clone()));
+    assert!(key.matches_purge("example.com", "/foo"));
+    assert!(key.matches_purge("example.com", "/f*"));
+    assert!(!key.matches_purge("example.com", "/foo/*"));
+    assert!(!key.matches_purge("example.org", "/foo"));
+  }
+
+  #[rstest]
+  #[case(&[("Authorization", "Basic Zm9vOmJhcg==")])]
+  #[case(&[("Cookie", "theme=dark; PHPSESSID=abc")])]
+  #[case(&[("Cookie", "wordpress_logged_in_0123abcd=admin")])]
+  fn test_key_bypass(#[case] headers: &[(&str, &str)]) {
+    assert_eq!(cache().key(&request(headers)), None);
+
+    let mut request = request(&[]);
+    *request.method_mut() = hyper::Method::POST;
+    assert_eq!(cache().key(&request), None);
+  }
+
+  #[rstest]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60")], Some((60, 10)))]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60, stale-while-revalidate=30")], Some((60, 30)))]
+  #[case(StatusCode::NOT_FOUND, &[("Cache-Control", "public"), ("Cache-Control", "s-maxage=5")], Some((5, 10)))]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60"), ("Vary", "Accept-Encoding, Accept-Language, Cookie")], Some((60, 10)))]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, max-age=60")], None)]
+  #[case(StatusCode::OK, &[("Cache-Control", "s-maxage=60")], None)]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=0")], None)]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60, no-store")], None)]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60"), ("Set-Cookie", "a=b")], None)]
+  #[case(StatusCode::OK, &[("Cache-Control", "public, s-maxage=60"), ("Vary", "User-Agent")], None)]
+  #[case(StatusCode::INTERNAL_SERVER_ERROR, &[("Cache-Control", "public, s-maxage=60")], None)]
+  fn test_lifetimes(#[case] status: StatusCode, #[case] headers: &[(&str, &str)], #[case] expected: Option<(u64, u64)>) {
+    let mut builder = Response::builder().status(status);
+    for (name, value) in headers {
+      builder = builder.header(*name, *value);
+    }
+    let expected = expected.map(|(fresh, stale)| Lifetimes {
+      fresh: Duration::from_secs(fresh),
+      stale: Duration::from_secs(stale),
+    });
+    assert_eq!(cache().lifetimes(&builder.body(()).unwrap()), expected);
+  }
+
+  #[test]
+  fn test_allows_purge() {
+    assert!(!cache().allows_purge("127.0.0.1".parse().unwrap()));
+
+    let cache: Cache = toml::from_str(r#"purge_from = ["127.0.0.1", "::1"]"#).unwrap();
+    assert!(cache.allows_purge("127.0.0.1".parse().unwrap()));
+    assert!(cache.allows_purge("::1".parse().unwrap()));
+    assert!(!cache.allows_purge("192.168.1.1".parse().unwrap()));
+  }
+}
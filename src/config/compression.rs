@@ -0,0 +1,202 @@
+use hyper::Response;
+use hyper::body::Body;
+use hyper::header::CONTENT_LENGTH;
+use hyper::header::CONTENT_TYPE;
+use serde::Deserialize;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::Predicate;
+use tower_http::compression::predicate::NotForContentType;
+
+/// Marks a response that must not be compressed, set by routes with `action.compress = false`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NoCompression;
+
+/// Dynamic compression of responses, negotiated with the `Accept-Encoding` request header.
+///
+/// Only responses of an allowed MIME type and at least `min_size` bytes are compressed. Streamed
+/// responses of unknown size are always candidates, except server-sent events, which must reach
+/// the client as each event is written.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Compression {
+  #[serde(default = "default_enabled")]
+  enabled: bool,
+  #[serde(default = "default_algorithms")]
+  algorithms: Vec<Algorithm>,
+  #[serde(default = "default_types")]
+  types: Vec<String>,
+  #[serde(default = "default_min_size")]
+  min_size: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Algorithm {
+  Gzip,
+  Br,
+  Zstd,
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+  vec![Algorithm::Zstd, Algorithm::Br, Algorithm::Gzip]
+}
+
+fn default_types() -> Vec<String> {
+  [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/*+json",
+    "application/*+xml",
+    "image/svg+xml",
+  ]
+  .map(str::to_string)
+  .to_vec()
+}
+
+fn default_min_size() -> u64 {
+  1024
+}
+
+impl Default for Compression {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      algorithms: default_algorithms(),
+      types: default_types(),
+      min_size: default_min_size(),
+    }
+  }
+}
+
+impl Compression {
+  pub(crate) fn layer(&self) -> CompressionLayer<Self> {
+    CompressionLayer::new()
+      .gzip(self.algorithms.contains(&Algorithm::Gzip))
+      .br(self.algorithms.contains(&Algorithm::Br))
+      .zstd(self.algorithms.contains(&Algorithm::Zstd))
+      .compress_when(self.clone())
+  }
+
+  pub(crate) fn min_size(&self) -> u64 {
+    self.min_size
+  }
+
+  pub(crate) fn allows_type(&self, content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    self.types.iter().any(|pattern| match pattern.split_once('*') {
+      Some((prefix, suffix)) => mime.len() > prefix.len() + suffix.len() && mime.starts_with(prefix) && mime.ends_with(suffix),
+      None => mime == pattern.to_ascii_lowercase(),
+    })
+  }
+}
+
+impl Predicate for Compression {
+  fn should_compress<B>(&self, response: &Response<B>) -> bool
+  where
+    B: Body,
+  {
+    if !self.enabled || response.extensions().get::<NoCompression>().is_some() || !NotForContentType::SSE.should_compress(response) {
+      return false;
+    }
+
+    let size = response.body().size_hint().exact().or_else(|| {
+      response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+    });
+    if size.is_some_and(|size| size < self.min_size) {
+      return false;
+    }
+
+    response
+      .headers()
+      .get(CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .is_some_and(|content_type| self.allows_type(content_type))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::Infallible;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Full;
+  use http_body_util::StreamBody;
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::body::Frame;
+  use rstest::rstest;
+  use tower::Layer;
+  use tower::ServiceExt;
+  use tower::service_fn;
+  use tower_http::compression::Predicate;
+
+  use crate::config::compression::Compression;
+  use crate::config::compression::NoCompression;
+  use crate::service::ResponseBody;
+
+  fn compression() -> Compression {
+    toml::from_str("min_size = 4").unwrap()
+  }
+
+  #[rstest]
+  #[case(Some("text/html; charset=UTF-8"), "<html>", true)]
+  #[case(Some("application/json"), "{\"a\":1}", true)]
+  #[case(Some("application/ld+json"), "{\"a\":1}", true)]
+  #[case(Some("image/svg+xml"), "<svg/>", true)]
+  #[case(Some("image/png"), "\u{89}PNG\r\n", false)]
+  #[case(Some("text/plain"), "foo", false)]
+  #[case(Some("text/event-stream"), "data: foo\n\n", false)]
+  #[case(None, "<html>", false)]
+  fn test_should_compress(#[case] content_type: Option<&str>, #[case] body: &'static str, #[case] expected: bool) {
+    let mut builder = Response::builder();
+    if let Some(content_type) = content_type {
+      builder = builder.header("Content-Type", content_type);
+    }
+    let response = builder.body(Full::from(body)).unwrap();
+    assert_eq!(compression().should_compress(&response), expected);
+  }
+
+  #[test]
+  fn test_should_not_compress() {
+    let mut response = Response::builder()
+      .header("Content-Type", "text/html")
+      .body(Full::from("<html>"))
+      .unwrap();
+    assert!(!Compression::default().should_compress(&response));
+
+    response.extensions_mut().insert(NoCompression);
+    assert!(!compression().should_compress(&response));
+  }
+
+  #[rstest]
+  #[case::full(Full::from("<html>".repeat(16)).boxed_unsync())]
+  #[case::chunked(StreamBody::new(futures_util::stream::iter(["<html>", "</html>"].map(|chunk| Ok(Frame::data(Bytes::from(chunk)))))).boxed_unsync())]
+  #[tokio::test]
+  async fn test_layer(#[case] body: ResponseBody) {
+    let mut body = Some(body);
+    let service = compression().layer().layer(service_fn(move |_| {
+      let body = body.take().unwrap();
+      async move { Ok::<_, Infallible>(Response::builder().header("Content-Type", "text/html").body(body).unwrap()) }
+    }));
+    let request = Request::builder()
+      .header("Accept-Encoding", "gzip")
+      .body(Full::<Bytes>::default())
+      .unwrap();
+
+    let response = service.oneshot(request).await.unwrap();
+    assert_eq!(response.headers()["Content-Encoding"], "gzip");
+    assert_eq!(response.headers()["Vary"], "accept-encoding");
+    assert!(!response.headers().contains_key("Content-Length"));
+    assert_eq!(&response.into_body().collect().await.unwrap().to_bytes()[..2], [0x1f, 0x8b]);
+  }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::ACCESS_CONTROL_ALLOW_CREDENTIALS;
+use hyper::header::ACCESS_CONTROL_ALLOW_HEADERS;
+use hyper::header::ACCESS_CONTROL_ALLOW_METHODS;
+use hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS;
+use hyper::header::ACCESS_CONTROL_MAX_AGE;
+use hyper::header::ACCESS_CONTROL_REQUEST_HEADERS;
+use hyper::header::ACCESS_CONTROL_REQUEST_METHOD;
+use hyper::header::ORIGIN;
+use hyper::header::VARY;
+use hyper::http::HeaderName;
+use hyper::http::HeaderValue;
+use regex::Regex;
+use regex::RegexBuilder;
+use serde::Deserialize;
+
+use crate::config::parse_duration;
+use crate::config::route::ApplyActions;
+use crate::config::route::ResponseHeaderAction;
+
+/// Cross-origin resource sharing policy of a route.
+///
+/// Origins are matched exactly, with `*` wildcards such as `https://*.example.com`, or as a regex
+/// when starting with `^`. A single `*` allows any origin, and cannot be combined with credentials, which
+/// browsers refuse for a wildcard origin.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "CorsConfig")]
+pub(crate) struct Cors {
+  origins: Vec<Regex>,
+  any_origin: bool,
+  methods: Vec<Method>,
+  headers: Vec<HeaderName>,
+  any_header: bool,
+  expose_headers: Vec<HeaderName>,
+  credentials: bool,
+  max_age: Option<Duration>,
+}
+
+impl Cors {
+  pub(crate) fn is_preflight<B>(request: &Request<B>) -> bool {
+    request.method() == Method::OPTIONS
+      && request.headers().contains_key(ORIGIN)
+      && request.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+  }
+
+  /// Answers a preflight request, without any CORS header when the origin or method is not allowed.
+  pub(crate) fn preflight<B, R: Default>(&self, request: &Request<B>) -> Response<R> {
+    let mut response = Response::<R>::default();
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.append(
+      VARY,
+      HeaderValue::from_static("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"),
+    );
+
+    let Some(origin) = self.allowed_origin(request) else {
+      return response;
+    };
+    let method = request
+      .headers()
+      .get(ACCESS_CONTROL_REQUEST_METHOD)
+      .and_then(|method| Method::from_bytes(method.as_bytes()).ok());
+    if !method.is_some_and(|method| self.methods.contains(&method)) {
+      return response;
+    }
+
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if self.credentials {
+      headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+    let methods = self.methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+    if let Ok(methods) = HeaderValue::from_str(&methods) {
+      headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    }
+    let allowed_headers = match (self.any_header, request.headers().get(ACCESS_CONTROL_REQUEST_HEADERS)) {
+      (true, Some(requested)) if self.credentials => Some(requested.clone()),
+      (true, _) => Some(HeaderValue::from_static("*")),
+      (false, _) => join_headers(&self.headers),
+    };
+    if let Some(allowed_headers) = allowed_headers {
+      headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+    }
+    if let Some(max_age) = self.max_age {
+      headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+    }
+    response
+  }
+
+  /// Headers decorating the actual response of a cross-origin request.
+  pub(crate) fn response_headers<B>(&self, request: &Request<B>) -> ResponseHeaderAction {
+    let mut insert = HashMap::new();
+    if let Some(origin) = self.allowed_origin(request) {
+      insert.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+      if self.credentials {
+        insert.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+      }
+      if let Some(expose_headers) = join_headers(&self.expose_headers) {
+        insert.insert(ACCESS_CONTROL_EXPOSE_HEADERS, expose_headers);
+      }
+    }
+
+    let mut append = HashMap::new();
+    append.insert(VARY, HeaderValue::from_static("Origin"));
+    ResponseHeaderAction::new(insert, append)
+  }
+
+  fn allowed_origin<B>(&self, request: &Request<B>) -> Option<HeaderValue> {
+    let origin = request.headers().get(ORIGIN)?;
+    if self.any_origin {
+      return Some(HeaderValue::from_static("*"));
+    }
+    let matches = origin
+      .to_str()
+      .is_ok_and(|origin| self.origins.iter().any(|regex| regex.is_match(origin)));
+    matches.then(|| origin.clone())
+  }
+}
+
+fn join_headers(headers: &[HeaderName]) -> Option<HeaderValue> {
+  if headers.is_empty() {
+    return None;
+  }
+  HeaderValue::from_str(&headers.iter().map(HeaderName::as_str).collect::<Vec<_>>().join(", ")).ok()
+}
+
+#[derive(Deserialize)]
+struct CorsConfig {
+  origins: Vec<String>,
+  #[serde(default = "default_methods")]
+  methods: Vec<String>,
+  #[serde(default)]
+  headers: Vec<String>,
+  #[serde(default)]
+  expose_headers: Vec<String>,
+  #[serde(default)]
+  credentials: bool,
+  #[serde(default)]
+  max_age: Option<String>,
+}
+
+fn default_methods() -> Vec<String> {
+  ["GET", "HEAD", "POST"].map(str::to_string).to_vec()
+}
+
+impl TryFrom<CorsConfig> for Cors {
+  type Error = anyhow::Error;
+
+  fn try_from(config: CorsConfig) -> Result<Self, Self::Error> {
+    let any_origin = config.origins.iter().any(|origin| origin == "*");
+    if any_origin && config.credentials {
+      anyhow::bail!("`cors` cannot allow credentials for any origin `*`, list the allowed origins instead");
+    }
+    let origins = config
+      .origins
+      .iter()
+      .filter(|origin| *origin != "*")
+      .map(|origin| {
+        let pattern = match origin.starts_with('^') {
+          true => origin.clone(),
+          false => format!("^{}$", regex::escape(origin).replace(r"\*", "[^/]*")),
+        };
+        RegexBuilder::new(&pattern).case_insensitive(true).build()
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let methods = config
+      .methods
+      .iter()
+      .map(|method| method.to_ascii_uppercase().parse::<Method>())
+      .collect::<Result<Vec<_>, _>>()?;
+    let any_header = config.headers.iter().any(|header| header == "*");
+    let headers = config
+      .headers
+      .iter()
+      .filter(|header| *header != "*")
+      .map(|header| header.parse::<HeaderName>())
+      .collect::<Result<Vec<_>, _>>()?;
+    let expose_headers = config
+      .expose_headers
+      .iter()
+      .map(|header| header.parse::<HeaderName>())
+      .collect::<Result<Vec<_>, _>>()?;
+    let max_age = config.max_age.as_deref().map(parse_duration).transpose()?;
+
+    Ok(Self {
+      origins,
+      any_origin,
+      methods,
+      headers,
+      any_header,
+      expose_headers,
+      credentials: config.credentials,
+      max_age,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use rstest::rstest;
+  use serde::Deserialize;
+
+  use crate::config::cors::Cors;
+  use crate::config::route::ApplyActions;
+
+  fn cors(config: &str) -> anyhow::Result<Cors> {
+    #[derive(Deserialize)]
+    struct Action {
+      cors: Cors,
+    }
+    Ok(toml::from_str::<Action>(&format!("cors = {{ {config} }}"))?.cors)
+  }
+
+  #[rstest]
+  #[case("https://example.com", Some("https://example.com"))]
+  #[case("https://api.example.com", Some("https://api.example.com"))]
+  #[case("https://evil.com", None)]
+  #[case("https://evil.com/.example.com", None)]
+  #[case("http://localhost:8080", Some("http://localhost:8080"))]
+  fn test_response_headers(#[case] origin: &str, #[case] expected: Option<&str>) {
+    let cors =
+      cors(r#"origins = ["https://example.com", "https://*.example.com", '^http://localhost(:\d+)?$'], credentials = true"#).unwrap();
+    let request = Request::builder().header("Origin", origin).body(String::default()).unwrap();
+    let mut response = Response::new(String::default());
+    response.headers_mut().insert("Vary", "Accept-Encoding".parse().unwrap());
+    cors.response_headers(&request).apply_actions(&mut response);
+
+    let headers = response.headers();
+    assert_eq!(headers.get("Access-Control-Allow-Origin").map(|value| value.to_str().unwrap()), expected);
+    assert_eq!(headers.contains_key("Access-Control-Allow-Credentials"), expected.is_some());
+    assert_eq!(headers.get_all("Vary").iter().collect::<Vec<_>>(), ["Accept-Encoding", "Origin"]);
+  }
+
+  #[test]
+  fn test_any_origin() {
+    let request = Request::builder()
+      .header("Origin", "https://example.com")
+      .body(String::default())
+      .unwrap();
+
+    let mut response = Response::new(String::default());
+    cors(r#"origins = ["*"], expose_headers = ["X-Total"]"#)
+      .unwrap()
+      .response_headers(&request)
+      .apply_actions(&mut response);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    assert_eq!(response.headers()["Access-Control-Expose-Headers"], "x-total");
+  }
+
+  #[rstest]
+  #[case("https://example.com", "PUT", true)]
+  #[case("https://example.com", "DELETE", false)]
+  #[case("https://evil.com", "PUT", false)]
+  fn test_preflight(#[case] origin: &str, #[case] method: &str, #[case] allowed: bool) {
+    let cors =
+      cors(r#"origins = ["https://example.com"], methods = ["get", "put"], headers = ["Content-Type", "X-Token"], max_age = "1h""#)
+        .unwrap();
+    let request = Request::builder()
+      .method("OPTIONS")
+      .header("Origin", origin)
+      .header("Access-Control-Request-Method", method)
+      .body(String::default())
+      .unwrap();
+    assert!(Cors::is_preflight(&request));
+
+    let response = cors.preflight::<_, String>(&request);
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let headers = response.headers();
+    assert_eq!(headers.contains_key("Access-Control-Allow-Origin"), allowed);
+    if allowed {
+      assert_eq!(headers["Access-Control-Allow-Methods"], "GET, PUT");
+      assert_eq!(headers["Access-Control-Allow-Headers"], "content-type, x-token");
+      assert_eq!(headers["Access-Control-Max-Age"], "3600");
+    }
+  }
+
+  #[test]
+  fn test_is_preflight() {
+    let request = Request::builder()
+      .method("OPTIONS")
+      .header("Origin", "https://example.com")
+      .body(String::default())
+      .unwrap();
+    assert!(!Cors::is_preflight(&request));
+  }
+
+  #[test]
+  fn test_invalid() {
+    assert!(cors(r#"origins = ['^(']"#).is_err());
+    assert!(cors(r#"origins = ["*"], methods = ["GET POST"]"#).is_err());
+    assert!(cors(r#"origins = ["*"], max_age = "soon""#).is_err());
+    assert!(cors(r#"origins = ["*"], credentials = true"#).is_err());
+  }
+}
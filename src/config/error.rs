@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use hyper::StatusCode;
+use serde::Deserialize;
+
+/// Pages served in place of error responses without a body, keyed by status code.
+///
+/// A page is either a static file or, when it ends with `.php`, a PHP script, both relative to the
+/// document root.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "HashMap<String, String>")]
+pub(crate) struct ErrorPages(HashMap<StatusCode, ErrorPage>);
+
+/// Requests the details of PHP errors to be rendered on the error response, enabled by
+/// `debug_errors` in `pasir.toml`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DebugErrors;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ErrorPage {
+  Php(String),
+  Static(String),
+}
+
+impl ErrorPages {
+  pub(crate) fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub(crate) fn get(&self, status: StatusCode) -> Option<&ErrorPage> {
+    self.0.get(&status)
+  }
+}
+
+impl TryFrom<HashMap<String, String>> for ErrorPages {
+  type Error = anyhow::Error;
+
+  fn try_from(map: HashMap<String, String>) -> Result<Self, Self::Error> {
+    map
+      .into_iter()
+      .map(|(status, page)| {
+        let status = StatusCode::from_bytes(status.as_bytes())?;
+        if !status.is_client_error() && !status.is_server_error() {
+          anyhow::bail!("Error page can only be set for error status codes: {status}");
+        }
+        if !page.starts_with('/') {
+          anyhow::bail!("Error page must be an absolute path from the document root: {page}");
+        }
+
+        let page = match page.ends_with(".php") {
+          true => ErrorPage::Php(page),
+          false => ErrorPage::Static(page),
+        };
+        Ok((status, page))
+      })
+      .collect::<anyhow::Result<HashMap<StatusCode, ErrorPage>>>()
+      .map(Self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use hyper::StatusCode;
+
+  use crate::config::error::ErrorPage;
+  use crate::config::error::ErrorPages;
+
+  #[test]
+  fn test_error_pages() {
+    let pages: ErrorPages = toml::from_str(
+      r#"
+      404 = "/index.php"
+      500 = "/errors/500.html"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(pages.get(StatusCode::NOT_FOUND), Some(&ErrorPage::Php("/index.php".to_string())));
+    assert_eq!(
+      pages.get(StatusCode::INTERNAL_SERVER_ERROR),
+      Some(&ErrorPage::Static("/errors/500.html".to_string()))
+    );
+    assert_eq!(pages.get(StatusCode::BAD_GATEWAY), None);
+  }
+
+  #[test]
+  fn test_invalid_error_pages() {
+    assert!(ErrorPages::try_from(HashMap::from([("foo".to_string(), "/500.html".to_string())])).is_err());
+    assert!(ErrorPages::try_from(HashMap::from([("200".to_string(), "/200.html".to_string())])).is_err());
+    assert!(ErrorPages::try_from(HashMap::from([("500".to_string(), "500.html".to_string())])).is_err());
+  }
+}
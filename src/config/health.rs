@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::config::deserialize_duration;
+
+/// Built-in liveness and readiness probes, configured by the top-level `[health]` table.
+///
+/// Probes are answered once `enabled`. The liveness probe answers as long as the process serves
+/// requests. The readiness probe starts a PHP request, or runs `script` when set, within `timeout`,
+/// and fails for `drain_period` before the listener closes on shutdown.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Health {
+  #[serde(default)]
+  enabled: bool,
+  #[serde(default = "default_liveness")]
+  liveness: String,
+  #[serde(default = "default_readiness")]
+  readiness: String,
+  #[serde(default)]
+  script: Option<String>,
+  #[serde(default = "default_timeout", deserialize_with = "deserialize_duration")]
+  timeout: Duration,
+  #[serde(default = "default_drain_period", deserialize_with = "deserialize_duration")]
+  drain_period: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Probe {
+  Liveness,
+  Readiness,
+}
+
+fn default_liveness() -> String {
+  "/healthz".to_string()
+}
+
+fn default_readiness() -> String {
+  "/readyz".to_string()
+}
+
+fn default_timeout() -> Duration {
+  Duration::from_secs(5)
+}
+
+fn default_drain_period() -> Duration {
+  Duration::from_secs(5)
+}
+
+impl Default for Health {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      liveness: default_liveness(),
+      readiness: default_readiness(),
+      script: None,
+      timeout: default_timeout(),
+      drain_period: default_drain_period(),
+    }
+  }
+}
+
+impl Health {
+  pub(crate) fn validate(&self) -> anyhow::Result<()> {
+    for path in [&self.liveness, &self.readiness].into_iter().chain(&self.script) {
+      if !path.starts_with('/') {
+        anyhow::bail!("Health path `{path}` must start with `/`");
+      }
+    }
+    Ok(())
+  }
+
+  /// Probe answered at `path`, if any.
+  pub(crate) fn probe(&self, path: &str) -> Option<Probe> {
+    match path {
+      _ if !self.enabled => None,
+      path if path == self.liveness => Some(Probe::Liveness),
+      path if path == self.readiness => Some(Probe::Readiness),
+      _ => None,
+    }
+  }
+
+  /// PHP script run by the readiness probe, as a path from the document root.
+  pub(crate) fn script(&self) -> Option<&str> {
+    self.script.as_deref()
+  }
+
+  pub(crate) fn timeout(&self) -> Duration {
+    self.timeout
+  }
+
+  /// Time new connections are still accepted once shutdown starts, for load balancers to notice the
+  /// failing readiness probe. Zero when the probes are disabled.
+  pub(crate) fn drain_period(&self) -> Duration {
+    match self.enabled {
+      true => self.drain_period,
+      false => Duration::ZERO,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use rstest::rstest;
+
+  use crate::config::health::Health;
+  use crate::config::health::Probe;
+
+  #[rstest]
+  #[case("enabled = true", "/healthz", Some(Probe::Liveness))]
+  #[case("enabled = true", "/readyz", Some(Probe::Readiness))]
+  #[case("enabled = true", "/healthz/", None)]
+  #[case("", "/healthz", None)]
+  #[case(
+    "enabled = true\nliveness = \"/-/alive\"\nreadiness = \"/-/ready\"",
+    "/-/ready",
+    Some(Probe::Readiness)
+  )]
+  #[case("enabled = true\nliveness = \"/-/alive\"", "/healthz", None)]
+  fn test_probe(#[case] config: &str, #[case] path: &str, #[case] expected: Option<Probe>) {
+    let health: Health = toml::from_str(config).unwrap();
+    assert_eq!(health.probe(path), expected);
+  }
+
+  #[test]
+  fn test_validate() {
+    let health: Health = toml::from_str("script = \"/health.php\"\ntimeout = \"2s\"").unwrap();
+    assert!(health.validate().is_ok());
+    assert_eq!(health.timeout(), Duration::from_secs(2));
+
+    let health: Health = toml::from_str("script = \"health.php\"").unwrap();
+    assert!(health.validate().is_err());
+  }
+
+  #[rstest]
+  #[case("", Duration::ZERO)]
+  #[case("enabled = true", Duration::from_secs(5))]
+  #[case("enabled = true\ndrain_period = \"0s\"", Duration::ZERO)]
+  fn test_drain_period(#[case] config: &str, #[case] expected: Duration) {
+    let health: Health = toml::from_str(config).unwrap();
+    assert_eq!(health.drain_period(), expected);
+  }
+}
@@ -1 +1,75 @@
+pub mod access;
+pub mod cache;
+pub mod compression;
+pub mod cors;
+pub mod error;
+pub mod health;
+pub mod rate_limit;
 pub mod route;
+pub mod site;
+pub mod static_files;
+pub mod upstream;
+
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde::Deserializer;
+
+/// Deserializes a duration written as `<number><unit>`, where the unit is one of `ms`, `s`, `m` or `h`.
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  parse_duration(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+pub(crate) fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+  let value = value.trim();
+  let index = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+  let (number, unit) = value.split_at(index);
+  let number = number.parse::<u64>()?;
+
+  let seconds = |factor: u64| {
+    number
+      .checked_mul(factor)
+      .map(Duration::from_secs)
+      .with_context(|| format!("Duration is too long: {value}"))
+  };
+  match unit.trim() {
+    "ms" => Ok(Duration::from_millis(number)),
+    "" | "s" => Ok(Duration::from_secs(number)),
+    "m" => seconds(60),
+    "h" => seconds(60 * 60),
+    unit => anyhow::bail!("Unknown duration unit: {unit}"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use rstest::rstest;
+
+  use crate::config::parse_duration;
+
+  #[rstest]
+  #[case("500ms", Duration::from_millis(500))]
+  #[case("10", Duration::from_secs(10))]
+  #[case("10s", Duration::from_secs(10))]
+  #[case("2m", Duration::from_secs(120))]
+  #[case("1h", Duration::from_secs(3600))]
+  fn test_parse_duration(#[case] value: &str, #[case] expected: Duration) {
+    assert_eq!(parse_duration(value).unwrap(), expected);
+  }
+
+  #[rstest]
+  #[case("")]
+  #[case("s")]
+  #[case("10d")]
+  #[case("307445734561825861m")]
+  #[case("5124095576030432h")]
+  fn test_parse_duration_invalid(#[case] value: &str) {
+    assert!(parse_duration(value).is_err());
+  }
+}
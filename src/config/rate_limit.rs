@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use headers::Cookie;
+use headers::HeaderMapExt;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::RETRY_AFTER;
+use hyper::http::HeaderName;
+use hyper::http::HeaderValue;
+use serde::Deserialize;
+
+use crate::cli::serve::Stream;
+use crate::config::deserialize_duration;
+use crate::config::route::ApplyActions;
+
+/// Buckets kept before idle ones are evicted.
+const MIN_SWEEP: usize = 1024;
+
+/// Token bucket rate limit of a route, with one bucket per client.
+///
+/// A bucket holds up to `burst` tokens, refilled at `requests` per `per`, and every request takes
+/// one. Buckets that have refilled completely are evicted as the table grows.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "RateLimitConfig")]
+pub(crate) struct RateLimit(Arc<RateLimiter>);
+
+#[derive(Debug)]
+struct RateLimiter {
+  capacity: f64,
+  rate: f64,
+  key: RateLimitKey,
+  buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+  map: HashMap<String, Bucket>,
+  sweep_at: usize,
+}
+
+#[derive(Debug)]
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+
+#[derive(Debug, PartialEq)]
+enum RateLimitKey {
+  Ip,
+  Header(HeaderName),
+  Cookie(String),
+}
+
+/// State of the client bucket after a request, reported in the `RateLimit-*` headers.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RateLimitStatus {
+  limit: u32,
+  remaining: u32,
+  reset: Duration,
+  retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+  /// Takes a token from the bucket of the client, failing with the bucket state when it is empty.
+  pub(crate) fn acquire<B>(&self, request: &Request<B>) -> Result<RateLimitStatus, RateLimitStatus> {
+    self.acquire_at(&self.0.key.extract(request), Instant::now())
+  }
+
+  /// Gives back the token taken by `acquire`, when another rate limit rejected the request.
+  pub(crate) fn release<B>(&self, request: &Request<B>) {
+    self.release_key(&self.0.key.extract(request));
+  }
+
+  fn acquire_at(&self, key: &str, now: Instant) -> Result<RateLimitStatus, RateLimitStatus> {
+    let limiter = &self.0;
+    let mut buckets = limiter.buckets.lock().unwrap();
+    if buckets.map.len() >= buckets.sweep_at {
+      buckets.map.retain(|_, bucket| limiter.refill(bucket, now) < limiter.capacity);
+      buckets.sweep_at = (buckets.map.len() * 2).max(MIN_SWEEP);
+    }
+
+    let bucket = buckets.map.entry(key.to_string()).or_insert(Bucket {
+      tokens: limiter.capacity,
+      updated: now,
+    });
+    bucket.tokens = limiter.refill(bucket, now);
+    bucket.updated = now;
+    let allowed = bucket.tokens >= 1.0;
+    if allowed {
+      bucket.tokens -= 1.0;
+    }
+
+    let status = RateLimitStatus {
+      limit: limiter.capacity as u32,
+      remaining: bucket.tokens.floor() as u32,
+      reset: Duration::from_secs_f64((limiter.capacity - bucket.tokens) / limiter.rate),
+      retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / limiter.rate)),
+    };
+    match allowed {
+      true => Ok(status),
+      false => Err(status),
+    }
+  }
+
+  fn release_key(&self, key: &str) {
+    let limiter = &self.0;
+    if let Some(bucket) = limiter.buckets.lock().unwrap().map.get_mut(key) {
+      bucket.tokens = (bucket.tokens + 1.0).min(limiter.capacity);
+    }
+  }
+}
+
+impl RateLimiter {
+  fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * self.rate).min(self.capacity)
+  }
+}
+
+impl RateLimitKey {
+  /// Identifies the client, falling back to its address when the header or cookie is missing.
+  fn extract<B>(&self, request: &Request<B>) -> String {
+    let value = match self {
+      RateLimitKey::Ip => None,
+      RateLimitKey::Header(name) => request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string),
+      RateLimitKey::Cookie(name) => request
+        .headers()
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(name).map(str::to_string)),
+    };
+
+    match value {
+      Some(value) => format!("key:{value}"),
+      None => {
+        let addr = request
+          .extensions()
+          .get::<Arc<Stream>>()
+          .map(|stream| stream.peer_addr().ip().to_string());
+        format!("ip:{}", addr.unwrap_or_default())
+      }
+    }
+  }
+}
+
+impl RateLimitStatus {
+  pub(crate) fn remaining(&self) -> u32 {
+    self.remaining
+  }
+
+  pub(crate) fn into_response<B: Default>(self) -> Response<B> {
+    let mut response = Response::<B>::default();
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    self.apply_actions(&mut response);
+    response
+  }
+}
+
+impl ApplyActions for RateLimitStatus {
+  fn apply_actions<B>(&self, response: &mut Response<B>) {
+    let headers = response.headers_mut();
+    headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(self.reset.as_secs_f64().ceil() as u64));
+    if let Some(retry_after) = self.retry_after {
+      headers.insert(RETRY_AFTER, HeaderValue::from((retry_after.as_secs_f64().ceil() as u64).max(1)));
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct RateLimitConfig {
+  requests: u32,
+  #[serde(deserialize_with = "deserialize_duration")]
+  per: Duration,
+  #[serde(default)]
+  burst: Option<u32>,
+  #[serde(default)]
+  key: Option<String>,
+}
+
+impl TryFrom<RateLimitConfig> for RateLimit {
+  type Error = anyhow::Error;
+
+  fn try_from(config: RateLimitConfig) -> Result<Self, Self::Error> {
+    if config.requests == 0 || config.per.is_zero() {
+      anyhow::bail!("`rate_limit` requires a positive `requests` and `per`");
+    }
+
+    let key = match config.key.as_deref().unwrap_or("ip") {
+      "ip" => RateLimitKey::Ip,
+      key => match key.split_once(':') {
+        Some(("header", name)) => RateLimitKey::Header(name.trim().parse()?),
+        Some(("cookie", name)) => RateLimitKey::Cookie(name.trim().to_string()),
+        _ => anyhow::bail!("`rate_limit.key` must be `ip`, `header:<name>` or `cookie:<name>`"),
+      },
+    };
+
+    Ok(Self(Arc::new(RateLimiter {
+      capacity: f64::from(config.burst.unwrap_or(config.requests).max(1)),
+      rate: f64::from(config.requests) / config.per.as_secs_f64(),
+      key,
+      buckets: Mutex::new(Buckets {
+        map: HashMap::new(),
+        sweep_at: MIN_SWEEP,
+      }),
+    })))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use std::time::Duration;
+  use std::time::Instant;
+
+  use hyper::Request;
+  use hyper::StatusCode;
+  use rstest::rstest;
+  use serde::Deserialize;
+
+  use crate::cli::serve::Stream;
+  use crate::config::rate_limit::MIN_SWEEP;
+  use crate::config::rate_limit::RateLimit;
+
+  fn rate_limit(config: &str) -> anyhow::Result<RateLimit> {
+    #[derive(Deserialize)]
+    struct Action {
+      rate_limit: RateLimit,
+    }
+    Ok(toml::from_str::<Action>(&format!("rate_limit = {{ {config} }}"))?.rate_limit)
+  }
+
+  #[test]
+  fn test_acquire() {
+    let limit = rate_limit(r#"requests = 1, per = "1s", burst = 2"#).unwrap();
+    let now = Instant::now();
+
+    assert_eq!(limit.acquire_at("a", now).unwrap().remaining(), 1);
+    assert_eq!(limit.acquire_at("a", now).unwrap().remaining(), 0);
+    let exceeded = limit.acquire_at("a", now).unwrap_err();
+    assert_eq!(exceeded.retry_after, Some(Duration::from_secs(1)));
+    assert_eq!(exceeded.reset, Duration::from_secs(2));
+    assert!(limit.acquire_at("b", now).is_ok());
+
+    assert!(limit.acquire_at("a", now + Duration::from_secs(1)).is_ok());
+    assert!(limit.acquire_at("a", now + Duration::from_secs(1)).is_err());
+  }
+
+  #[test]
+  fn test_release() {
+    let limit = rate_limit(r#"requests = 1, per = "1s", burst = 2"#).unwrap();
+    let now = Instant::now();
+
+    assert_eq!(limit.acquire_at("a", now).unwrap().remaining(), 1);
+    limit.release_key("a");
+    limit.release_key("a");
+    limit.release_key("b");
+    assert_eq!(limit.acquire_at("a", now).unwrap().remaining(), 1);
+    assert_eq!(limit.acquire_at("a", now).unwrap().remaining(), 0);
+    assert!(limit.acquire_at("a", now).is_err());
+  }
+
+  #[test]
+  fn test_eviction() {
+    let limit = rate_limit(r#"requests = 10, per = "1s""#).unwrap();
+    let now = Instant::now();
+    for key in 0..MIN_SWEEP {
+      limit.acquire_at(&key.to_string(), now).unwrap();
+    }
+    limit.acquire_at("a", now + Duration::from_secs(1)).unwrap();
+
+    let buckets = limit.0.buckets.lock().unwrap();
+    assert_eq!(buckets.map.len(), 1);
+    assert_eq!(buckets.sweep_at, MIN_SWEEP);
+  }
+
+  #[test]
+  fn test_into_response() {
+    let limit = rate_limit(r#"requests = 1, per = "2s""#).unwrap();
+    let now = Instant::now();
+    limit.acquire_at("a", now).unwrap();
+    let response = limit.acquire_at("a", now).unwrap_err().into_response::<String>();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "2");
+    assert_eq!(response.headers()["ratelimit-limit"], "1");
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+    assert_eq!(response.headers()["ratelimit-reset"], "2");
+  }
+
+  #[rstest]
+  #[case(r#""ip""#, None, "ip:127.0.0.1")]
+  #[case(r#""header:X-Api-Key""#, Some(("X-Api-Key", "secret")), "key:secret")]
+  #[case(r#""header:X-Api-Key""#, None, "ip:127.0.0.1")]
+  #[case(r#""cookie:session""#, Some(("Cookie", "theme=dark; session=abc")), "key:abc")]
+  fn test_key(#[case] key: &str, #[case] header: Option<(&str, &str)>, #[case] expected: &str) {
+    let limit = rate_limit(&format!(r#"requests = 1, per = "1s", key = {key}"#)).unwrap();
+    let mut builder = Request::builder().extension(Arc::new(Stream::default()));
+    if let Some((name, value)) = header {
+      builder = builder.header(name, value);
+    }
+    let request = builder.body(String::default()).unwrap();
+    assert_eq!(limit.0.key.extract(&request), expected);
+  }
+
+  #[rstest]
+  #[case(r#"requests = 0, per = "1s""#)]
+  #[case(r#"requests = 1, per = "0s""#)]
+  #[case(r#"requests = 1, per = "1s", key = "user""#)]
+  #[case(r#"requests = 1, per = "1s", key = "query:id""#)]
+  fn test_invalid(#[case] config: &str) {
+    assert!(rate_limit(config).is_err());
+  }
+}
@@ -1,7 +1,15 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::Component;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
 
 use anyhow::Context;
+use headers::HeaderMapExt;
+use headers::Host;
 use hyper::Request;
 use hyper::Response;
 use hyper::StatusCode;
@@ -14,11 +22,54 @@ use serde::Deserializer;
 use tracing::info;
 use tracing::warn;
 
+use crate::cli::serve::Stream;
+use crate::config::access::AccessDenied;
+use crate::config::access::Auth;
+use crate::config::access::CidrList;
+use crate::config::access::CredentialCheck;
+use crate::config::cache::Cache;
+use crate::config::compression::Compression;
+use crate::config::cors::Cors;
+use crate::config::error::ErrorPages;
+use crate::config::health::Health;
+use crate::config::rate_limit::RateLimit;
+use crate::config::rate_limit::RateLimitStatus;
+use crate::config::site::Site;
+use crate::config::static_files::StaticFiles;
+use crate::config::static_files::percent_decode;
+use crate::config::upstream::Upstreams;
+use crate::sapi::context::IniEntries;
+use crate::sapi::context::RequestVariables;
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub(crate) struct Routes {
+  #[serde(default)]
   routes: Vec<Route>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  #[serde(default)]
+  errors: Arc<ErrorPages>,
+  #[serde(default)]
+  sites: Vec<Site>,
+  #[serde(default)]
+  compression: Option<Compression>,
+  #[serde(default, rename = "static")]
+  static_files: Arc<StaticFiles>,
+  #[serde(default)]
+  etag: Option<bool>,
+  #[serde(default)]
+  server_timing: Option<bool>,
+  #[serde(default)]
+  debug_errors: Option<bool>,
+  #[serde(default)]
+  cache: Option<Cache>,
+  #[serde(default)]
+  health: Option<Health>,
 }
 
+/// Health checks of routes without a `[health]` table.
+static DEFAULT_HEALTH: LazyLock<Health> = LazyLock::new(Health::default);
+
 impl Routes {
   pub(crate) fn from_file(path: PathBuf) -> anyhow::Result<Self> {
     info!("Reading routes from {:?}", path);
@@ -29,18 +80,260 @@ impl Routes {
       return Ok(Self::default());
     }
 
-    let routes = toml::from_str(&content?).with_context(|| format!("Failed to parse routes from: {path:?}"))?;
+    let mut routes: Self = toml::from_str(&content?).with_context(|| format!("Failed to parse routes from: {path:?}"))?;
+    routes.validate()?;
+    routes.resolve(path.parent().unwrap_or(Path::new(".")))?;
     info!("Routes loaded from {:?}", path);
 
     Ok(routes)
   }
 
-  pub(crate) fn served_route<B>(&self, request: &Request<B>) -> Option<Route> {
+  pub(crate) fn validate(&self) -> anyhow::Result<()> {
+    self.health().validate()?;
+    for site in &self.sites {
+      site.validate()?;
+    }
+    for (index, route) in self.routes.iter().enumerate() {
+      if matches!(route.serve, Some(RouteServe::TryFiles)) && route.try_files().is_none() {
+        anyhow::bail!("Route #{index} is served with `try_files` but has no `action.try_files` list");
+      }
+      if matches!(route.serve, Some(RouteServe::Proxy)) && route.upstreams().is_none() {
+        anyhow::bail!("Route #{index} is served with `proxy` but has no `action.upstream`");
+      }
+      if let Some(action) = &route.action {
+        let names = route.route_match.variable_names();
+        if let Some(name) = action
+          .request_headers
+          .templates()
+          .flat_map(template_variables)
+          .find(|name| !names.contains(*name))
+        {
+          anyhow::bail!("Route #{index} request header template refers to unknown variable `${name}`");
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Resolves the sites and files referenced by the routes relative to the directory of the configuration file.
+  pub(crate) fn resolve(&mut self, base: &Path) -> anyhow::Result<()> {
+    for route in &mut self.routes {
+      if let Some(auth) = route.action.as_mut().and_then(|action| action.auth.as_mut()) {
+        auth.load(base)?;
+      }
+    }
+    for site in &mut self.sites {
+      site.resolve_root(base)?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn has_sites(&self) -> bool {
+    !self.sites.is_empty()
+  }
+
+  pub(crate) fn has_cache(&self) -> bool {
+    self.cache.is_some()
+  }
+
+  pub(crate) fn cache(&self) -> Option<&Cache> {
+    self.cache.as_ref()
+  }
+
+  pub(crate) fn has_health(&self) -> bool {
+    self.health.is_some()
+  }
+
+  pub(crate) fn health(&self) -> &Health {
+    self.health.as_ref().unwrap_or(&DEFAULT_HEALTH)
+  }
+
+  pub(crate) fn has_compression(&self) -> bool {
+    self.compression.is_some()
+  }
+
+  pub(crate) fn compression(&self) -> Compression {
+    self.compression.clone().unwrap_or_default()
+  }
+
+  pub(crate) fn static_files(&self) -> &Arc<StaticFiles> {
+    &self.static_files
+  }
+
+  /// Whether full PHP responses get a weak `ETag`, as configured by the site serving the request or
+  /// the top level.
+  pub(crate) fn etag<B>(&self, request: &Request<B>) -> bool {
+    self
+      .site(request)
+      .and_then(|site| site.routes().etag)
+      .or(self.etag)
+      .unwrap_or_default()
+  }
+
+  /// Whether PHP responses get a `Server-Timing` header, as configured by the site serving the
+  /// request or the top level.
+  pub(crate) fn server_timing<B>(&self, request: &Request<B>) -> bool {
+    self
+      .site(request)
+      .and_then(|site| site.routes().server_timing)
+      .or(self.server_timing)
+      .unwrap_or_default()
+  }
+
+  /// Whether PHP errors are rendered on a development error page, as configured by the site serving
+  /// the request or the top level.
+  pub(crate) fn debug_errors<B>(&self, request: &Request<B>) -> bool {
+    self
+      .site(request)
+      .and_then(|site| site.routes().debug_errors)
+      .or(self.debug_errors)
+      .unwrap_or_default()
+  }
+
+  pub(crate) fn errors(&self) -> &Arc<ErrorPages> {
+    &self.errors
+  }
+
+  pub(crate) fn site<B>(&self, request: &Request<B>) -> Option<&Site> {
+    if self.sites.is_empty() {
+      return None;
+    }
+    let host = request_host(request)?;
+    self.sites.iter().find(|site| site.matches_host(&host))
+  }
+
+  /// Resolves the virtual host serving the request, pointing the request's root and routes at the site.
+  pub(crate) fn resolve_site<B>(&self, request: &mut Request<B>) -> Option<&Site> {
+    let site = self.site(request)?;
+    if !self.env.is_empty() {
+      let variables = request.extensions_mut().get_or_insert_default::<RequestVariables>();
+      variables.env.extend(self.env.clone());
+    }
+    site.apply(request);
+    Some(site)
+  }
+
+  /// Checks the deny lists and authentication of the routes matching the request, up to the served
+  /// route, exposing an authenticated user as `REMOTE_USER` and `AUTH_TYPE`.
+  pub(crate) fn authorize<B>(&self, request: &mut Request<B>) -> Result<(), AccessDenied> {
+    for route in &self.routes {
+      if route.matches_request(request) {
+        route.authorize(request)?;
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Verifications that the credentials of the request need before `authorize`, to be run on the
+  /// blocking pool.
+  pub(crate) fn credential_checks<B>(&self, request: &Request<B>) -> Vec<CredentialCheck> {
+    let mut checks = vec![];
+    for route in &self.routes {
+      if route.matches_request(request) {
+        // `authorize` refuses the request before checking the credentials of later routes.
+        if route.denies(request) {
+          break;
+        }
+        if let Some(auth) = route.action.as_ref().and_then(|action| action.auth.as_ref()) {
+          checks.extend(auth.credential_check(request));
+        }
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
+    checks
+  }
+
+  /// Takes a token from the rate limits of the routes matching the request, up to the served route,
+  /// returning the state of the most depleted bucket. When a bucket is empty, the tokens already taken
+  /// are given back, so a rejected request does not drain the other limits.
+  pub(crate) fn rate_limit<B>(&self, request: &Request<B>) -> Result<Option<RateLimitStatus>, RateLimitStatus> {
+    let mut acquired: Vec<&RateLimit> = vec![];
+    let mut status: Option<RateLimitStatus> = None;
+    for route in &self.routes {
+      if route.matches_request(request) {
+        if let Some(rate_limit) = route.action.as_ref().and_then(|action| action.rate_limit.as_ref()) {
+          let current = match rate_limit.acquire(request) {
+            Ok(current) => current,
+            Err(exceeded) => {
+              acquired.iter().for_each(|rate_limit| rate_limit.release(request));
+              return Err(exceeded);
+            }
+          };
+          acquired.push(rate_limit);
+          if status.as_ref().is_none_or(|status| current.remaining() < status.remaining()) {
+            status = Some(current);
+          }
+        }
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
+    Ok(status)
+  }
+
+  /// CORS policy of the first route matching the request, up to the served route.
+  pub(crate) fn cors<B>(&self, request: &Request<B>) -> Option<&Cors> {
+    for route in &self.routes {
+      if route.matches_request(request) {
+        if let Some(cors) = route.action.as_ref().and_then(|action| action.cors.as_ref()) {
+          return Some(cors);
+        }
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
+    None
+  }
+
+  /// Whether the response may be compressed, i.e. no route matching the request up to the served
+  /// route sets `action.compress = false`.
+  pub(crate) fn compress<B>(&self, request: &Request<B>) -> bool {
+    for route in &self.routes {
+      if route.matches_request(request) {
+        if route.action.as_ref().and_then(|action| action.compress) == Some(false) {
+          return false;
+        }
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
+    true
+  }
+
+  /// The first route serving the request, with its index.
+  pub(crate) fn served_route<B>(&self, request: &Request<B>) -> Option<(usize, Route)> {
     self
       .routes
       .iter()
-      .find(|route| route.serve.is_some() && route.matches_request(request))
-      .cloned()
+      .enumerate()
+      .find(|(_, route)| route.serve.is_some() && route.matches_request(request))
+      .map(|(index, route)| (index, route.clone()))
+  }
+}
+
+impl ApplyRequestActions for Routes {
+  fn apply_request_actions<B>(&self, request: &mut Request<B>) {
+    if !self.env.is_empty() {
+      let variables = request.extensions_mut().get_or_insert_default::<RequestVariables>();
+      variables.env.extend(self.env.clone());
+    }
+
+    for route in &self.routes {
+      if route.matches_request(request) {
+        route.apply_request_actions(request);
+        if route.serve.is_some() {
+          break;
+        }
+      }
+    }
   }
 }
 
@@ -68,6 +361,38 @@ impl Route {
   pub(crate) fn serve(&mut self) -> RouteServe {
     self.serve.take().unwrap()
   }
+
+  pub(crate) fn try_files(&self) -> Option<&TryFiles> {
+    self.action.as_ref()?.try_files.as_ref()
+  }
+
+  pub(crate) fn upstreams(&self) -> Option<&Upstreams> {
+    self.action.as_ref()?.upstream.as_ref()
+  }
+
+  fn authorize<B>(&self, request: &mut Request<B>) -> Result<(), AccessDenied> {
+    let Some(action) = &self.action else {
+      return Ok(());
+    };
+
+    if self.denies(request) {
+      return Err(AccessDenied::Forbidden);
+    }
+    if let Some(auth) = &action.auth {
+      let user = auth.authenticate(request)?;
+      let variables = request.extensions_mut().get_or_insert_default::<RequestVariables>();
+      variables.server.insert("REMOTE_USER".to_string(), user);
+      variables.server.insert("AUTH_TYPE".to_string(), "Basic".to_string());
+    }
+    Ok(())
+  }
+
+  fn denies<B>(&self, request: &Request<B>) -> bool {
+    let Some(deny) = self.action.as_ref().and_then(|action| action.deny.as_ref()) else {
+      return false;
+    };
+    remote_addr(request).is_none_or(|addr| deny.contains(addr))
+  }
 }
 
 impl MatchesRequest for Route {
@@ -82,6 +407,29 @@ impl MatchesResponse for Route {
   }
 }
 
+impl ApplyRequestActions for Route {
+  fn apply_request_actions<B>(&self, request: &mut Request<B>) {
+    let Some(action) = &self.action else {
+      return;
+    };
+
+    if !action.request_headers.is_empty() {
+      let variables = self.route_match.variables(request);
+      action.request_headers.apply(request, &variables);
+    }
+    if !action.server_vars.is_empty() || !action.env.is_empty() {
+      let variables = request.extensions_mut().get_or_insert_default::<RequestVariables>();
+      variables.server.extend(action.server_vars.clone());
+      variables.env.extend(action.env.clone());
+    }
+    if !action.php_ini.is_empty() || !action.php_admin_ini.is_empty() {
+      let ini = request.extensions_mut().get_or_insert_default::<IniEntries>();
+      ini.values.extend(action.php_ini.clone());
+      ini.admin_values.extend(action.php_admin_ini.clone());
+    }
+  }
+}
+
 impl ApplyActions for Route {
   fn apply_actions<B>(&self, response: &mut Response<B>) {
     if let Some(action) = &self.action {
@@ -95,14 +443,84 @@ impl ApplyActions for Route {
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub(crate) struct RouteMatch {
-  #[serde(default, deserialize_with = "deserialize_uri")]
+  #[serde(default, deserialize_with = "deserialize_regex")]
   uri: Option<Regex>,
+  #[serde(default, deserialize_with = "deserialize_regex")]
+  host: Option<Regex>,
+  #[serde(default)]
+  remote_addr: Option<CidrList>,
   #[serde(default, deserialize_with = "deserialize_headers")]
   response_headers: HashMap<HeaderName, Regex>,
 }
 
+impl RouteMatch {
+  /// Collects the values request header templates can refer to: connection info, the `uri` captures
+  /// by index or name, and the named `host` captures.
+  fn variables<B>(&self, request: &Request<B>) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+    if let Some(stream) = request.extensions().get::<Arc<Stream>>() {
+      variables.insert("remote_addr".to_string(), stream.peer_addr().ip().to_string());
+      variables.insert("remote_port".to_string(), stream.peer_addr().port().to_string());
+      variables.insert("server_addr".to_string(), stream.local_addr().ip().to_string());
+      variables.insert("server_port".to_string(), stream.local_addr().port().to_string());
+    }
+
+    if let Some(regex) = &self.host
+      && let Some(host) = request_host(request)
+      && let Some(captures) = regex.captures(&host)
+    {
+      for name in regex.capture_names().flatten() {
+        if let Some(value) = captures.name(name) {
+          variables.insert(name.to_string(), value.as_str().to_string());
+        }
+      }
+    }
+
+    if let Some(regex) = &self.uri
+      && let Some(captures) = regex.captures(request.uri().path())
+    {
+      for (index, name) in regex.capture_names().enumerate() {
+        if let Some(value) = captures.get(index) {
+          variables.insert(index.to_string(), value.as_str().to_string());
+          if let Some(name) = name {
+            variables.insert(name.to_string(), value.as_str().to_string());
+          }
+        }
+      }
+    }
+
+    variables
+  }
+
+  /// Names of the variables `variables` may collect, for templates to be checked at load time.
+  fn variable_names(&self) -> HashSet<String> {
+    let mut names = HashSet::from(["remote_addr", "remote_port", "server_addr", "server_port"].map(str::to_string));
+    if let Some(regex) = &self.host {
+      names.extend(regex.capture_names().flatten().map(str::to_string));
+    }
+    if let Some(regex) = &self.uri {
+      for (index, name) in regex.capture_names().enumerate() {
+        names.insert(index.to_string());
+        names.extend(name.map(str::to_string));
+      }
+    }
+    names
+  }
+}
+
 impl MatchesRequest for RouteMatch {
   fn matches_request<B>(&self, request: &Request<B>) -> bool {
+    if let Some(regex) = &self.host
+      && !request_host(request).is_some_and(|host| regex.is_match(&host))
+    {
+      return false;
+    }
+    if let Some(list) = &self.remote_addr
+      && !remote_addr(request).is_some_and(|addr| list.contains(addr))
+    {
+      return false;
+    }
+
     match &self.uri {
       None => true,
       Some(regex) => regex.is_match(request.uri().path()),
@@ -129,7 +547,108 @@ pub(crate) struct RouteAction {
   #[serde(default, deserialize_with = "deserialize_status")]
   status: Option<StatusCode>,
   #[serde(default)]
+  request_headers: RequestHeaderAction,
+  #[serde(default)]
   response_headers: ResponseHeaderAction,
+  #[serde(default)]
+  server_vars: HashMap<String, String>,
+  #[serde(default)]
+  env: HashMap<String, String>,
+  #[serde(default, deserialize_with = "deserialize_ini")]
+  php_ini: HashMap<String, String>,
+  #[serde(default, deserialize_with = "deserialize_ini")]
+  php_admin_ini: HashMap<String, String>,
+  #[serde(default)]
+  try_files: Option<TryFiles>,
+  #[serde(default)]
+  upstream: Option<Upstreams>,
+  #[serde(default)]
+  deny: Option<CidrList>,
+  #[serde(default)]
+  auth: Option<Auth>,
+  #[serde(default)]
+  rate_limit: Option<RateLimit>,
+  #[serde(default)]
+  cors: Option<Cors>,
+  #[serde(default)]
+  compress: Option<bool>,
+}
+
+type RequestHeaderActionOption = Option<HashMap<HeaderName, String>>;
+
+/// Request header manipulation applied before the request is dispatched.
+///
+/// Values are templates that may refer to `$remote_addr`, `$remote_port`, `$server_addr`,
+/// `$server_port` and the route match captures, e.g. `$1` or `${tenant}`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct RequestHeaderAction {
+  #[serde(default, deserialize_with = "deserialize_request_action_headers")]
+  insert: RequestHeaderActionOption,
+  #[serde(default, deserialize_with = "deserialize_request_action_headers")]
+  append: RequestHeaderActionOption,
+  remove: Option<Vec<String>>,
+}
+
+impl RequestHeaderAction {
+  fn is_empty(&self) -> bool {
+    self.insert.is_none() && self.append.is_none() && self.remove.is_none()
+  }
+
+  fn templates(&self) -> impl Iterator<Item = &str> {
+    self.insert.iter().chain(&self.append).flat_map(HashMap::values).map(String::as_str)
+  }
+
+  fn apply<B>(&self, request: &mut Request<B>, variables: &HashMap<String, String>) {
+    if let Some(remove) = &self.remove {
+      for key in remove {
+        request.headers_mut().remove(key);
+      }
+    }
+    if let Some(insert) = &self.insert {
+      for (key, template) in insert {
+        if let Some(value) = expand_template(template, variables) {
+          request.headers_mut().insert(key, value);
+        }
+      }
+    }
+    if let Some(append) = &self.append {
+      for (key, template) in append {
+        if let Some(value) = expand_template(template, variables) {
+          request.headers_mut().append(key, value);
+        }
+      }
+    }
+  }
+}
+
+static TEMPLATE_VARIABLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$(?:\{(\w+)\}|(\w+))").unwrap());
+
+fn expand_template(template: &str, variables: &HashMap<String, String>) -> Option<HeaderValue> {
+  let value = TEMPLATE_VARIABLE.replace_all(template, |captures: &regex::Captures| {
+    let name = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str();
+    variables.get(name).cloned().unwrap_or_default()
+  });
+  HeaderValue::from_str(&value)
+    .inspect_err(|_| warn!("Invalid request header value expanded from template: {template}"))
+    .ok()
+}
+
+/// Names of the variables a request header template refers to.
+fn template_variables(template: &str) -> impl Iterator<Item = &str> {
+  TEMPLATE_VARIABLE
+    .captures_iter(template)
+    .map(|captures| captures.get(1).or_else(|| captures.get(2)).unwrap().as_str())
+}
+
+pub(crate) fn remote_addr<B>(request: &Request<B>) -> Option<IpAddr> {
+  request.extensions().get::<Arc<Stream>>().map(|stream| stream.peer_addr().ip())
+}
+
+pub(crate) fn request_host<B>(request: &Request<B>) -> Option<String> {
+  match request.headers().typed_get::<Host>() {
+    Some(host) => Some(host.hostname().to_string()),
+    None => request.uri().host().map(str::to_string),
+  }
 }
 
 type ResponseHeaderActionOption = Option<HashMap<HeaderName, HeaderValue>>;
@@ -143,6 +662,16 @@ pub(crate) struct ResponseHeaderAction {
   remove: Option<Vec<String>>,
 }
 
+impl ResponseHeaderAction {
+  pub(crate) fn new(insert: HashMap<HeaderName, HeaderValue>, append: HashMap<HeaderName, HeaderValue>) -> Self {
+    Self {
+      insert: Some(insert),
+      append: Some(append),
+      remove: None,
+    }
+  }
+}
+
 impl ApplyActions for ResponseHeaderAction {
   fn apply_actions<B>(&self, response: &mut Response<B>) {
     if let Some(insert) = &self.insert {
@@ -169,9 +698,102 @@ pub(crate) enum RouteServe {
   Php,
   Default,
   Static,
+  #[serde(rename = "try_files")]
+  TryFiles,
+  Proxy,
+  Status,
+}
+
+/// Ordered list of candidate paths, the last entry being the fallback used when no candidate exists.
+///
+/// Candidates may reference the percent-decoded request path with `$uri`. The fallback is either a URI, which is
+/// served by PHP when it ends with `.php` and as a static file otherwise, or `=<status>`. Entries escaping the
+/// document root are skipped, or answered with `404 Not Found` for the fallback.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub(crate) struct TryFiles {
+  candidates: Vec<String>,
+  fallback: TryFilesFallback,
+}
+
+impl TryFiles {
+  pub(crate) fn resolve(&self, root: &Path, uri: &str) -> TryFilesTarget {
+    let uri = percent_decode(uri);
+    for candidate in &self.candidates {
+      let path = candidate.replace("$uri", &uri);
+      if let Some(file) = join_root(root, &path)
+        && file.is_file()
+      {
+        return TryFilesTarget::from_path(path);
+      }
+    }
+
+    match &self.fallback {
+      TryFilesFallback::Uri(fallback) => {
+        let path = fallback.replace("$uri", &uri);
+        match join_root(root, &path) {
+          Some(_) => TryFilesTarget::from_path(path),
+          None => TryFilesTarget::Status(StatusCode::NOT_FOUND),
+        }
+      }
+      TryFilesFallback::Status(status) => TryFilesTarget::Status(*status),
+    }
+  }
 }
 
-fn deserialize_uri<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+impl TryFrom<Vec<String>> for TryFiles {
+  type Error = anyhow::Error;
+
+  fn try_from(mut candidates: Vec<String>) -> Result<Self, Self::Error> {
+    let fallback = match candidates.pop() {
+      None => anyhow::bail!("`try_files` requires at least one entry"),
+      Some(fallback) => match fallback.strip_prefix('=') {
+        Some(status) => TryFilesFallback::Status(StatusCode::from_bytes(status.as_bytes())?),
+        None => TryFilesFallback::Uri(fallback),
+      },
+    };
+
+    Ok(Self { candidates, fallback })
+  }
+}
+
+#[derive(Clone, Debug)]
+enum TryFilesFallback {
+  Uri(String),
+  Status(StatusCode),
+}
+
+/// Index of the route that served a request, `None` for the implicit fallback, set as a response
+/// extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ServedRoute(pub(crate) Option<usize>);
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum TryFilesTarget {
+  Php(String),
+  Static(String),
+  Status(StatusCode),
+}
+
+impl TryFilesTarget {
+  pub(crate) fn from_path(path: String) -> Self {
+    match path.ends_with(".php") {
+      true => Self::Php(path),
+      false => Self::Static(path),
+    }
+  }
+}
+
+/// Joins a URI path onto the document root, refusing paths that would escape it.
+pub(crate) fn join_root(root: &Path, path: &str) -> Option<PathBuf> {
+  let relative = Path::new(path.trim_start_matches('/'));
+  if relative.components().any(|component| !matches!(component, Component::Normal(_))) {
+    return None;
+  }
+  Some(root.join(relative))
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
 where
   D: Deserializer<'de>,
 {
@@ -182,6 +804,34 @@ where
     .map_err(serde::de::Error::custom)
 }
 
+pub(crate) fn deserialize_ini<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum IniValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+  }
+
+  let map = HashMap::<String, IniValue>::deserialize(deserializer)?;
+  Ok(
+    map
+      .into_iter()
+      .map(|(key, value)| {
+        let value = match value {
+          IniValue::String(value) => value,
+          IniValue::Integer(value) => value.to_string(),
+          IniValue::Boolean(value) => if value { "1" } else { "0" }.to_string(),
+        };
+        (key, value)
+      })
+      .collect(),
+  )
+}
+
 fn deserialize_status<'de, D>(deserializer: D) -> Result<Option<StatusCode>, D::Error>
 where
   D: Deserializer<'de>,
@@ -203,6 +853,19 @@ where
     .map_err(serde::de::Error::custom)
 }
 
+fn deserialize_request_action_headers<'de, D>(deserializer: D) -> Result<RequestHeaderActionOption, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let vec = Vec::<HashMap<String, String>>::deserialize(deserializer)?;
+  let hash_map = vec.into_iter().flat_map(|m| m.into_iter()).collect::<HashMap<String, String>>();
+  hash_map
+    .into_iter()
+    .map(|(key, value)| Ok(Some((key.parse()?, value))))
+    .collect::<anyhow::Result<RequestHeaderActionOption>>()
+    .map_err(serde::de::Error::custom)
+}
+
 fn deserialize_action_headers<'de, D>(deserializer: D) -> Result<ResponseHeaderActionOption, D::Error>
 where
   D: Deserializer<'de>,
@@ -228,24 +891,39 @@ pub(crate) trait ApplyActions {
   fn apply_actions<B>(&self, response: &mut Response<B>);
 }
 
+pub(crate) trait ApplyRequestActions {
+  fn apply_request_actions<B>(&self, request: &mut Request<B>);
+}
+
 #[cfg(test)]
 mod tests {
   use std::collections::HashMap;
+  use std::net::IpAddr;
+  use std::net::SocketAddr;
+  use std::path::Path;
   use std::path::PathBuf;
   use std::str::FromStr;
+  use std::sync::Arc;
 
   use hyper::Request;
   use hyper::Response;
+  use hyper::StatusCode;
   use hyper::http::HeaderName;
   use hyper::http::HeaderValue;
   use regex::RegexBuilder;
   use rstest::rstest;
 
+  use crate::cli::serve::Stream;
+  use crate::config::route::ApplyRequestActions;
   use crate::config::route::MatchesRequest;
   use crate::config::route::MatchesResponse;
   use crate::config::route::Route;
   use crate::config::route::RouteMatch;
   use crate::config::route::Routes;
+  use crate::config::route::TryFiles;
+  use crate::config::route::TryFilesTarget;
+  use crate::sapi::context::IniEntries;
+  use crate::sapi::context::RequestVariables;
 
   #[test]
   fn test_default_routes() {
@@ -270,6 +948,8 @@ mod tests {
     let route = Route {
       route_match: RouteMatch {
         uri: Some(RegexBuilder::new(&match_uri).build().unwrap()),
+        host: None,
+        remote_addr: None,
         response_headers: Default::default(),
       },
       action: None,
@@ -291,6 +971,8 @@ mod tests {
     let route = Route {
       route_match: RouteMatch {
         uri: None,
+        host: None,
+        remote_addr: None,
         response_headers,
       },
       action: None,
@@ -307,4 +989,268 @@ mod tests {
 
     assert_eq!(route.matches_response(&response), expected);
   }
+
+  #[rstest]
+  #[case("/foo/foo.php", TryFilesTarget::Php("/foo/foo.php".to_string()))]
+  #[case("/foo", TryFilesTarget::Php("/foo/index.php".to_string()))]
+  #[case("/bar", TryFilesTarget::Php("/index.php".to_string()))]
+  #[case("/../Cargo.toml", TryFilesTarget::Php("/index.php".to_string()))]
+  #[case("/%66oo/%66oo.php", TryFilesTarget::Php("/foo/foo.php".to_string()))]
+  #[case("/%2e%2e/Cargo.toml", TryFilesTarget::Php("/index.php".to_string()))]
+  fn test_try_files_resolve(#[case] uri: &str, #[case] expected: TryFilesTarget) {
+    let root = PathBuf::from("tests/fixtures/root");
+    let try_files = TryFiles::try_from(vec!["$uri".to_string(), "$uri/index.php".to_string(), "/index.php".to_string()]).unwrap();
+    assert_eq!(try_files.resolve(&root, uri), expected);
+  }
+
+  #[test]
+  fn test_try_files_fallback() {
+    let root = PathBuf::from("tests/fixtures/root");
+    let try_files = TryFiles::try_from(vec!["$uri".to_string(), "=404".to_string()]).unwrap();
+    assert_eq!(try_files.resolve(&root, "/bar"), TryFilesTarget::Status(hyper::StatusCode::NOT_FOUND));
+
+    let try_files = TryFiles::try_from(vec!["$uri.html".to_string()]).unwrap();
+    assert_eq!(try_files.resolve(&root, "/bar"), TryFilesTarget::Static("/bar.html".to_string()));
+    assert_eq!(try_files.resolve(&root, "/%2E%2E/bar"), TryFilesTarget::Status(hyper::StatusCode::NOT_FOUND));
+
+    assert!(TryFiles::try_from(vec![]).is_err());
+    assert!(TryFiles::try_from(vec!["=foo".to_string()]).is_err());
+  }
+
+  #[rstest]
+  #[case(Some("example.com"), true)]
+  #[case(Some("example.com:8080"), true)]
+  #[case(Some("example.org"), false)]
+  #[case(None, false)]
+  fn test_route_match_host(#[case] host: Option<&str>, #[case] expected: bool) {
+    let routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match.host = '^example\.com$'
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+
+    let mut builder = Request::builder().uri("/");
+    if let Some(host) = host {
+      builder = builder.header("Host", host);
+    }
+    let request = builder.body(String::default()).unwrap();
+    assert_eq!(routes.served_route(&request).is_some(), expected);
+  }
+
+  #[test]
+  fn test_apply_request_actions() {
+    let routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match = { }
+      action.request_headers.remove = ["Proxy"]
+
+      [[routes]]
+      match.host = '^(?<tenant>[^.]+)\.example\.com$'
+      match.uri = '^/api/(v\d+)/'
+      action.request_headers.insert = [
+          { "X-Tenant" = "${tenant}" },
+          { "X-Api-Version" = "$1" },
+      ]
+      action.request_headers.append = [
+          { "X-Client" = "$remote_addr:$remote_port" },
+      ]
+      serve = "php"
+
+      [[routes]]
+      match = { }
+      action.request_headers.insert = [
+          { "X-Unreachable" = "foo" },
+      ]
+      "#,
+    )
+    .unwrap();
+
+    let mut request = Request::builder()
+      .uri("/api/v2/users")
+      .header("Host", "acme.example.com")
+      .header("Proxy", "http://evil.example.com")
+      .extension(Arc::new(Stream::default()))
+      .body(String::default())
+      .unwrap();
+    routes.apply_request_actions(&mut request);
+
+    let headers = request.headers();
+    assert!(!headers.contains_key("Proxy"));
+    assert_eq!(headers["X-Tenant"], "acme");
+    assert_eq!(headers["X-Api-Version"], "v2");
+    assert_eq!(headers["X-Client"], "127.0.0.1:0");
+    assert!(!headers.contains_key("X-Unreachable"));
+  }
+
+  #[rstest]
+  #[case(r#"{ "X-Client" = "$remote_addr:$server_port" }"#, true)]
+  #[case(r#"{ "X-Tenant" = "${tenant}-$1" }"#, true)]
+  #[case(r#"{ "X-Api-Version" = "$2" }"#, false)]
+  #[case(r#"{ "X-Host" = "$host" }"#, false)]
+  fn test_validate_templates(#[case] header: &str, #[case] valid: bool) {
+    let routes: Routes = toml::from_str(&format!(
+      r#"
+      [[routes]]
+      match.host = '^(?<tenant>[^.]+)\.example\.com$'
+      match.uri = '^/api/(v\d+)/'
+      action.request_headers.insert = [{header}]
+      serve = "php"
+      "#
+    ))
+    .unwrap();
+    assert_eq!(routes.validate().is_ok(), valid);
+  }
+
+  #[test]
+  fn test_request_variables() {
+    let routes: Routes = toml::from_str(
+      r#"
+      [env]
+      APP_ENV = "dev"
+      APP_DEBUG = "1"
+
+      [[routes]]
+      match.uri = '^/admin/'
+      action.server_vars = { HTTPS = "on" }
+      action.env = { APP_ENV = "prod" }
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+
+    let mut request = Request::builder().uri("/admin/").body(String::default()).unwrap();
+    routes.apply_request_actions(&mut request);
+    let variables = request.extensions().get::<RequestVariables>().unwrap();
+    assert_eq!(variables.server.get("HTTPS").map(String::as_str), Some("on"));
+    assert_eq!(variables.env.get("APP_ENV").map(String::as_str), Some("prod"));
+    assert_eq!(variables.env.get("APP_DEBUG").map(String::as_str), Some("1"));
+
+    let mut request = Request::builder().uri("/").body(String::default()).unwrap();
+    routes.apply_request_actions(&mut request);
+    let variables = request.extensions().get::<RequestVariables>().unwrap();
+    assert!(variables.server.is_empty());
+    assert_eq!(variables.env.get("APP_ENV").map(String::as_str), Some("dev"));
+  }
+
+  #[test]
+  fn test_ini_entries() {
+    let routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match.uri = '^/import'
+      action.php_ini = { memory_limit = "1G", max_execution_time = 300 }
+      action.php_admin_ini = { display_errors = false }
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+
+    let mut request = Request::builder().uri("/import").body(String::default()).unwrap();
+    routes.apply_request_actions(&mut request);
+    let ini = request.extensions().get::<IniEntries>().unwrap();
+    assert_eq!(ini.values.get("memory_limit").map(String::as_str), Some("1G"));
+    assert_eq!(ini.values.get("max_execution_time").map(String::as_str), Some("300"));
+    assert_eq!(ini.admin_values.get("display_errors").map(String::as_str), Some("0"));
+
+    let mut request = Request::builder().uri("/").body(String::default()).unwrap();
+    routes.apply_request_actions(&mut request);
+    assert!(request.extensions().get::<IniEntries>().is_none());
+  }
+
+  #[rstest]
+  #[case("127.0.0.1", None, Some(StatusCode::UNAUTHORIZED))]
+  #[case("127.0.0.1", Some("Basic YWxpY2U6c2VjcmV0"), None)]
+  #[case("10.0.0.1", Some("Basic YWxpY2U6c2VjcmV0"), Some(StatusCode::FORBIDDEN))]
+  #[case("192.168.1.1", None, None)]
+  fn test_authorize(#[case] peer: IpAddr, #[case] authorization: Option<&str>, #[case] expected: Option<StatusCode>) {
+    let mut routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match.uri = '^/admin/'
+      match.remote_addr = ["192.168.0.0/16"]
+      serve = "php"
+
+      [[routes]]
+      match.uri = '^/admin/'
+      action.deny = ["10.0.0.0/8"]
+      action.auth = { basic = { realm = "Admin", htpasswd = "htpasswd" } }
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+    routes.resolve(Path::new("tests/fixtures")).unwrap();
+
+    let mut builder = Request::builder()
+      .uri("/admin/")
+      .extension(Arc::new(Stream::new(SocketAddr::new(peer, 80), SocketAddr::new(peer, 12345))));
+    if let Some(authorization) = authorization {
+      builder = builder.header("Authorization", authorization);
+    }
+    let mut request = builder.body(String::default()).unwrap();
+
+    let result = routes.authorize(&mut request);
+    assert_eq!(result.err().map(|denied| denied.into_response::<String>().status()), expected);
+    let variables = request.extensions().get::<RequestVariables>();
+    match authorization.is_some() && expected.is_none() {
+      true => {
+        let variables = variables.unwrap();
+        assert_eq!(variables.server.get("REMOTE_USER").map(String::as_str), Some("alice"));
+        assert_eq!(variables.server.get("AUTH_TYPE").map(String::as_str), Some("Basic"));
+      }
+      false => assert!(variables.is_none()),
+    }
+  }
+
+  #[test]
+  fn test_rate_limit() {
+    let routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match.uri = '^/login'
+      action.rate_limit = { requests = 1, per = "1m" }
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+
+    let request = Request::builder().uri("/").body(String::default()).unwrap();
+    assert_eq!(routes.rate_limit(&request), Ok(None));
+
+    let request = Request::builder().uri("/login").body(String::default()).unwrap();
+    assert_eq!(routes.rate_limit(&request).unwrap().map(|status| status.remaining()), Some(0));
+    let response = routes.rate_limit(&request).unwrap_err().into_response::<String>();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "60");
+  }
+
+  #[test]
+  fn test_rate_limit_all_or_nothing() {
+    let routes: Routes = toml::from_str(
+      r#"
+      [[routes]]
+      match.uri = '^/'
+      action.rate_limit = { requests = 10, per = "1m" }
+
+      [[routes]]
+      match.uri = '^/login'
+      action.rate_limit = { requests = 1, per = "1m" }
+      serve = "php"
+      "#,
+    )
+    .unwrap();
+
+    let request = Request::builder().uri("/login").body(String::default()).unwrap();
+    assert_eq!(routes.rate_limit(&request).unwrap().map(|status| status.remaining()), Some(0));
+    for _ in 0..3 {
+      assert!(routes.rate_limit(&request).is_err());
+    }
+
+    // The rejected logins left the site-wide bucket untouched.
+    let request = Request::builder().uri("/").body(String::default()).unwrap();
+    assert_eq!(routes.rate_limit(&request).unwrap().map(|status| status.remaining()), Some(8));
+  }
 }
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use hyper::Request;
+use serde::Deserialize;
+
+use crate::config::route::Routes;
+use crate::config::route::deserialize_ini;
+use crate::sapi::context::IniEntries;
+
+/// A virtual host with its own document root and routes, selected by the request host.
+///
+/// Host patterns are matched case-insensitively, either exactly, as `*.example.com` to match any
+/// subdomain, or as `*` to match every host.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Site {
+  hosts: Vec<String>,
+  root: Arc<PathBuf>,
+  #[serde(default, deserialize_with = "deserialize_ini")]
+  php_ini: HashMap<String, String>,
+  #[serde(default, deserialize_with = "deserialize_ini")]
+  php_admin_ini: HashMap<String, String>,
+  /// File PHP errors of the site are logged to, set as the `error_log` INI entry.
+  #[serde(default)]
+  log: Option<PathBuf>,
+  #[serde(flatten)]
+  routes: Arc<Routes>,
+}
+
+impl Site {
+  pub(crate) fn root(&self) -> &Path {
+    self.root.as_path()
+  }
+
+  pub(crate) fn routes(&self) -> &Arc<Routes> {
+    &self.routes
+  }
+
+  pub(crate) fn validate(&self) -> anyhow::Result<()> {
+    if self.hosts.is_empty() {
+      anyhow::bail!("Site {:?} has no `hosts`", self.root);
+    }
+    if self.routes.has_sites() {
+      anyhow::bail!("Site {:?} cannot contain nested `sites`", self.root);
+    }
+    if self.routes.has_compression() {
+      anyhow::bail!("Site {:?} cannot configure `compression`, which applies to every site", self.root);
+    }
+    if self.routes.has_cache() {
+      anyhow::bail!("Site {:?} cannot configure `cache`, which applies to every site", self.root);
+    }
+    if self.routes.has_health() {
+      anyhow::bail!("Site {:?} cannot configure `health`, which applies to every site", self.root);
+    }
+    self
+      .routes
+      .validate()
+      .with_context(|| format!("Invalid routes for site {:?}", self.root))
+  }
+
+  /// Resolves the site root, log file and the files referenced by its routes relative to the directory
+  /// of the configuration file.
+  pub(crate) fn resolve_root(&mut self, base: &Path) -> anyhow::Result<()> {
+    Arc::make_mut(&mut self.routes).resolve(base)?;
+    if let Some(log) = &mut self.log {
+      *log = base.join(&*log);
+    }
+    let root = base.join(self.root.as_path());
+    let root = root
+      .canonicalize()
+      .with_context(|| format!("Failed to resolve site root: {root:?}"))?;
+    if !root.is_dir() {
+      anyhow::bail!("Site root is not a directory: {root:?}");
+    }
+    self.root = Arc::new(root);
+    Ok(())
+  }
+
+  pub(crate) fn matches_host(&self, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    self.hosts.iter().any(|pattern| {
+      let pattern = pattern.to_ascii_lowercase();
+      match pattern.strip_prefix('*') {
+        Some("") => true,
+        Some(suffix) if suffix.starts_with('.') => host.len() > suffix.len() && host.ends_with(suffix),
+        _ => pattern == host,
+      }
+    })
+  }
+
+  pub(crate) fn apply<B>(&self, request: &mut Request<B>) {
+    request.extensions_mut().insert(self.root.clone());
+    request.extensions_mut().insert(self.routes.clone());
+
+    if !self.php_ini.is_empty() || !self.php_admin_ini.is_empty() || self.log.is_some() {
+      let ini = request.extensions_mut().get_or_insert_default::<IniEntries>();
+      ini.values.extend(self.php_ini.clone());
+      ini.admin_values.extend(self.php_admin_ini.clone());
+      if let Some(log) = &self.log {
+        ini.admin_values.insert("error_log".to_string(), log.to_string_lossy().to_string());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use std::path::PathBuf;
+  use std::sync::Arc;
+
+  use hyper::Request;
+  use rstest::rstest;
+
+  use crate::config::route::Routes;
+  use crate::sapi::context::IniEntries;
+
+  fn routes() -> Routes {
+    toml::from_str(
+      r#"
+      [[sites]]
+      hosts = ["example.com", "*.example.com"]
+      root = "tests/fixtures/root"
+      php_ini = { memory_limit = "256M" }
+      log = "/var/log/example.log"
+
+      [[sites.routes]]
+      match.uri = '^/admin'
+      action.status = 403
+      serve = "default"
+
+      [[sites]]
+      hosts = ["*"]
+      root = "tests/fixtures"
+      "#,
+    )
+    .unwrap()
+  }
+
+  #[rstest]
+  #[case("example.com", Some("tests/fixtures/root"))]
+  #[case("EXAMPLE.com:8080", Some("tests/fixtures/root"))]
+  #[case("www.example.com", Some("tests/fixtures/root"))]
+  #[case("notexample.com", Some("tests/fixtures"))]
+  fn test_resolve_site(#[case] host: &str, #[case] root: Option<&str>) {
+    let routes = routes();
+    let mut request = Request::builder().uri("/admin").header("Host", host).body(()).unwrap();
+    let site = routes.resolve_site(&mut request);
+    assert_eq!(site.map(|site| site.root().to_path_buf()), root.map(PathBuf::from));
+    assert_eq!(
+      request.extensions().get::<Arc<PathBuf>>().map(|root| root.as_path().to_path_buf()),
+      root.map(PathBuf::from)
+    );
+  }
+
+  #[test]
+  fn test_site_apply() {
+    let routes = routes();
+    let mut request = Request::builder().uri("/admin").header("Host", "example.com").body(()).unwrap();
+    assert!(routes.resolve_site(&mut request).is_some());
+
+    let site_routes = request.extensions().get::<Arc<Routes>>().unwrap();
+    assert!(site_routes.served_route(&request).is_some());
+
+    let ini = request.extensions().get::<IniEntries>().unwrap();
+    assert_eq!(ini.values.get("memory_limit").map(String::as_str), Some("256M"));
+    assert_eq!(ini.admin_values.get("error_log").map(String::as_str), Some("/var/log/example.log"));
+  }
+
+  #[rstest]
+  #[case("serve = \"try_files\"")]
+  #[case("serve = \"proxy\"")]
+  fn test_validate_site_routes(#[case] serve: &str) {
+    let invalid: Routes = toml::from_str(&format!(
+      r#"
+      [[sites]]
+      hosts = ["example.com"]
+      root = "tests/fixtures/root"
+
+      [[sites.routes]]
+      match.uri = '^/'
+      {serve}
+      "#
+    ))
+    .unwrap();
+    assert!(invalid.validate().is_err());
+    assert!(routes().validate().is_ok());
+  }
+
+  #[rstest]
+  #[case("compression = {}")]
+  #[case("cache = {}")]
+  #[case("health = {}")]
+  fn test_validate_site_globals(#[case] table: &str) {
+    let invalid: Routes = toml::from_str(&format!(
+      r#"
+      [[sites]]
+      hosts = ["example.com"]
+      root = "tests/fixtures/root"
+      {table}
+      "#
+    ))
+    .unwrap();
+    assert!(invalid.validate().is_err());
+  }
+
+  #[test]
+  fn test_resolve_log() {
+    let mut routes: Routes = toml::from_str(
+      r#"
+      [[sites]]
+      hosts = ["example.com"]
+      root = "root"
+      log = "logs/example.log"
+      "#,
+    )
+    .unwrap();
+    routes.resolve(Path::new("tests/fixtures")).unwrap();
+
+    let mut request = Request::builder().uri("/").header("Host", "example.com").body(()).unwrap();
+    assert!(routes.resolve_site(&mut request).is_some());
+    let ini = request.extensions().get::<IniEntries>().unwrap();
+    assert_eq!(ini.admin_values.get("error_log").map(String::as_str), Some("tests/fixtures/logs/example.log"));
+  }
+
+  #[test]
+  fn test_without_host() {
+    let routes = routes();
+    let mut request = Request::builder().uri("/").body(()).unwrap();
+    assert!(routes.resolve_site(&mut request).is_none());
+  }
+}
@@ -0,0 +1,319 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use serde::Deserialize;
+use serde::Deserializer;
+
+use crate::config::route::TryFilesTarget;
+use crate::config::route::join_root;
+
+/// Options of the static file server, configured in the `[static]` table.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct StaticFiles {
+  /// Encodings of the precompressed siblings served in place of a file, e.g. `style.css.br`.
+  #[serde(default = "default_precompressed")]
+  precompressed: Vec<Encoding>,
+  /// File names served for a directory request, tried in order before falling back to PHP.
+  #[serde(default)]
+  index: Vec<String>,
+  /// Whether directories without an index file are listed.
+  #[serde(default)]
+  list_directories: bool,
+  /// MIME types by file extension, overriding the guessed ones.
+  #[serde(default, deserialize_with = "deserialize_by_extension")]
+  mime_types: HashMap<String, HeaderValue>,
+  /// `Cache-Control` of static responses by file extension, `*` applying to any other one.
+  #[serde(default, deserialize_with = "deserialize_by_extension")]
+  cache_control: HashMap<String, HeaderValue>,
+  /// Whether requests for dotfiles, VCS directories, package manifests and backups are answered with 404.
+  #[serde(default = "default_deny_sensitive")]
+  deny_sensitive: bool,
+}
+
+/// Package manager manifests and lock files that are never served.
+const SENSITIVE_FILES: [&str; 8] = [
+  "composer.json",
+  "composer.lock",
+  "package.json",
+  "package-lock.json",
+  "npm-shrinkwrap.json",
+  "yarn.lock",
+  "pnpm-lock.yaml",
+  "auth.json",
+];
+
+/// Suffixes of editor and manual backups, e.g. `wp-config.php~` or `settings.php.bak`.
+const BACKUP_SUFFIXES: [&str; 7] = ["~", ".bak", ".backup", ".old", ".orig", ".save", ".swp"];
+
+impl Default for StaticFiles {
+  fn default() -> Self {
+    Self {
+      precompressed: default_precompressed(),
+      index: Vec::new(),
+      list_directories: false,
+      mime_types: HashMap::new(),
+      cache_control: HashMap::new(),
+      deny_sensitive: default_deny_sensitive(),
+    }
+  }
+}
+
+fn default_precompressed() -> Vec<Encoding> {
+  vec![Encoding::Gzip]
+}
+
+fn default_deny_sensitive() -> bool {
+  true
+}
+
+impl StaticFiles {
+  pub(crate) fn precompressed(&self) -> &[Encoding] {
+    &self.precompressed
+  }
+
+  pub(crate) fn list_directories(&self) -> bool {
+    self.list_directories
+  }
+
+  /// Whether the request `path` must be hidden, checking each decoded segment so that `/%2eenv`
+  /// or `/.git/config` are caught. `.well-known` is allowed.
+  pub(crate) fn is_sensitive(&self, path: &str) -> bool {
+    if !self.deny_sensitive {
+      return false;
+    }
+
+    percent_decode(path).split(['/', '\\']).any(|segment| {
+      let segment = segment.to_ascii_lowercase();
+      (segment.starts_with('.') && !matches!(segment.as_str(), "." | ".." | ".well-known"))
+        || segment == "cvs"
+        || SENSITIVE_FILES.contains(&segment.as_str())
+        || BACKUP_SUFFIXES
+          .iter()
+          .any(|suffix| segment.len() > suffix.len() && segment.ends_with(suffix))
+    })
+  }
+
+  /// Resolves the first existing index file of the directory requested at `path`.
+  pub(crate) fn index(&self, root: &Path, path: &str) -> Option<TryFilesTarget> {
+    let path = percent_decode(path);
+    let directory = join_root(root, &path)?;
+    let path = path.trim_end_matches('/');
+    self
+      .index
+      .iter()
+      .find(|name| directory.join(name).is_file())
+      .map(|name| TryFilesTarget::from_path(format!("{path}/{name}")))
+  }
+
+  /// Applies the MIME type and `Cache-Control` overrides to the static response of `path`.
+  pub(crate) fn decorate<B>(&self, path: &str, response: &mut Response<B>) {
+    if !response.status().is_success() && response.status() != StatusCode::NOT_MODIFIED {
+      return;
+    }
+
+    let extension = Path::new(path)
+      .extension()
+      .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    let headers = response.headers_mut();
+    if let Some(mime_type) = extension.as_ref().and_then(|extension| self.mime_types.get(extension))
+      && headers.contains_key(CONTENT_TYPE)
+    {
+      headers.insert(CONTENT_TYPE, mime_type.clone());
+    }
+    if let Some(cache_control) = extension
+      .as_ref()
+      .and_then(|extension| self.cache_control.get(extension))
+      .or_else(|| self.cache_control.get("*"))
+      && !headers.contains_key(CACHE_CONTROL)
+    {
+      headers.insert(CACHE_CONTROL, cache_control.clone());
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Encoding {
+  Gzip,
+  Br,
+  Zstd,
+  Deflate,
+}
+
+impl Encoding {
+  /// Path of the precompressed sibling of `path`, following the naming `ServeDir` looks up.
+  pub(crate) fn sibling(self, path: &Path) -> PathBuf {
+    let extension = match self {
+      Encoding::Gzip => ".gz",
+      Encoding::Br => ".br",
+      Encoding::Zstd => ".zst",
+      Encoding::Deflate => ".zz",
+    };
+    let mut sibling = OsString::from(path.as_os_str());
+    sibling.push(extension);
+    PathBuf::from(sibling)
+  }
+}
+
+/// Decodes the percent-encoded bytes of a URI path, invalid UTF-8 being replaced.
+pub(crate) fn percent_decode(path: &str) -> Cow<'_, str> {
+  if !path.contains('%') {
+    return Cow::Borrowed(path);
+  }
+
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+  Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn deserialize_by_extension<'de, D>(deserializer: D) -> Result<HashMap<String, HeaderValue>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  HashMap::<String, String>::deserialize(deserializer)?
+    .into_iter()
+    .map(|(extension, value)| {
+      let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+      Ok((extension, value.parse()?))
+    })
+    .collect::<anyhow::Result<HashMap<String, HeaderValue>>>()
+    .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use std::path::PathBuf;
+
+  use hyper::Response;
+  use hyper::StatusCode;
+  use rstest::rstest;
+
+  use crate::config::route::TryFilesTarget;
+  use crate::config::static_files::Encoding;
+  use crate::config::static_files::StaticFiles;
+
+  fn static_files() -> StaticFiles {
+    toml::from_str(
+      r#"
+      index = ["index.html", "index.php"]
+      mime_types = { wasm = "application/wasm", ".MJS" = "text/javascript" }
+      cache_control = { css = "public, max-age=31536000, immutable", "*" = "public, max-age=60" }
+      "#,
+    )
+    .unwrap()
+  }
+
+  #[rstest]
+  #[case(Encoding::Gzip, "app.js.gz")]
+  #[case(Encoding::Br, "app.js.br")]
+  #[case(Encoding::Zstd, "app.js.zst")]
+  #[case(Encoding::Deflate, "app.js.zz")]
+  fn test_sibling(#[case] encoding: Encoding, #[case] expected: PathBuf) {
+    assert_eq!(encoding.sibling(Path::new("app.js")), expected);
+  }
+
+  #[test]
+  fn test_precompressed() {
+    assert_eq!(StaticFiles::default().precompressed(), [Encoding::Gzip]);
+
+    let static_files: StaticFiles = toml::from_str(r#"precompressed = ["br", "zstd", "gzip"]"#).unwrap();
+    assert_eq!(static_files.precompressed(), [Encoding::Br, Encoding::Zstd, Encoding::Gzip]);
+    assert!(toml::from_str::<StaticFiles>(r#"precompressed = ["lzma"]"#).is_err());
+  }
+
+  #[rstest]
+  #[case("/", Some(TryFilesTarget::Php("/index.php".to_string())))]
+  #[case("/foo/", Some(TryFilesTarget::Php("/foo/index.php".to_string())))]
+  #[case("/errors/", None)]
+  #[case("/../", None)]
+  fn test_index(#[case] path: &str, #[case] expected: Option<TryFilesTarget>) {
+    let root = PathBuf::from("tests/fixtures/root");
+    assert_eq!(static_files().index(&root, path), expected);
+    assert_eq!(StaticFiles::default().index(&root, path), None);
+  }
+
+  #[rstest]
+  #[case("/", false)]
+  #[case("/index.php", false)]
+  #[case("/assets/app.css", false)]
+  #[case("/.well-known/acme-challenge/token", false)]
+  #[case("/composer.php", false)]
+  #[case("/.env", true)]
+  #[case("/%2eenv", true)]
+  #[case("/%2Egit/config", true)]
+  #[case("/.git/config", true)]
+  #[case("/sub/.svn/entries", true)]
+  #[case("/CVS/Root", true)]
+  #[case("/composer.json", true)]
+  #[case("/vendor/Composer.lock", true)]
+  #[case("/package-lock.json", true)]
+  #[case("/wp-config.php~", true)]
+  #[case("/settings.php.bak", true)]
+  #[case("/index.php.orig", true)]
+  fn test_is_sensitive(#[case] path: &str, #[case] expected: bool) {
+    assert_eq!(StaticFiles::default().is_sensitive(path), expected);
+
+    let static_files: StaticFiles = toml::from_str("deny_sensitive = false").unwrap();
+    assert!(!static_files.is_sensitive(path));
+  }
+
+  #[rstest]
+  #[case("/app.wasm", StatusCode::OK, Some("application/wasm"), Some("public, max-age=60"))]
+  #[case("/app.mjs", StatusCode::OK, Some("text/javascript"), Some("public, max-age=60"))]
+  #[case("/app.CSS", StatusCode::OK, Some("text/plain"), Some("public, max-age=31536000, immutable"))]
+  #[case("/app.css", StatusCode::NOT_MODIFIED, None, Some("public, max-age=31536000, immutable"))]
+  #[case("/app.css", StatusCode::NOT_FOUND, Some("text/plain"), None)]
+  fn test_decorate(
+    #[case] path: &str,
+    #[case] status: StatusCode,
+    #[case] content_type: Option<&str>,
+    #[case] cache_control: Option<&str>,
+  ) {
+    let mut builder = Response::builder().status(status);
+    if status != StatusCode::NOT_MODIFIED {
+      builder = builder.header("Content-Type", "text/plain");
+    }
+    let mut response = builder.body(String::default()).unwrap();
+    static_files().decorate(path, &mut response);
+
+    let header = |name| response.headers().get(name).map(|value| value.to_str().unwrap());
+    assert_eq!(header("Content-Type"), content_type);
+    assert_eq!(header("Cache-Control"), cache_control);
+  }
+
+  #[test]
+  fn test_decorate_keeps_cache_control() {
+    let mut response = Response::builder()
+      .header("Cache-Control", "no-cache")
+      .body(String::default())
+      .unwrap();
+    static_files().decorate("/app.css", &mut response);
+    assert_eq!(response.headers()["Cache-Control"], "no-cache");
+  }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use hyper::Uri;
+use hyper::http::uri::PathAndQuery;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::config::deserialize_duration;
+
+/// Upstream servers of a `proxy` route, selected in round-robin order.
+///
+/// Health is checked passively: an upstream that fails `max_fails` consecutive requests is skipped
+/// for `fail_timeout`. When every upstream is down, the next one in line is tried regardless.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "UpstreamConfig")]
+pub(crate) struct Upstreams(Arc<UpstreamPool>);
+
+#[derive(Debug)]
+struct UpstreamPool {
+  servers: Vec<Upstream>,
+  next: AtomicUsize,
+  max_fails: u32,
+  fail_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Upstream {
+  uri: Uri,
+  fails: AtomicU32,
+  down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstreams {
+  /// Picks the next available upstream, returning its index and the URI to forward `uri` to.
+  pub(crate) fn select(&self, uri: &Uri) -> (usize, Uri) {
+    let pool = &self.0;
+    let len = pool.servers.len();
+    let start = pool.next.fetch_add(1, Ordering::Relaxed);
+    let index = (0..len)
+      .map(|offset| (start + offset) % len)
+      .find(|index| pool.servers[*index].is_available())
+      .unwrap_or(start % len);
+
+    (index, pool.servers[index].forward_uri(uri))
+  }
+
+  pub(crate) fn report(&self, index: usize, success: bool) {
+    let pool = &self.0;
+    let upstream = &pool.servers[index];
+    if success {
+      upstream.fails.store(0, Ordering::Relaxed);
+      return;
+    }
+
+    if upstream.fails.fetch_add(1, Ordering::Relaxed) + 1 >= pool.max_fails {
+      warn!("Upstream {} marked as down for {:?}", upstream.uri, pool.fail_timeout);
+      upstream.fails.store(0, Ordering::Relaxed);
+      *upstream.down_until.lock().unwrap() = Some(Instant::now() + pool.fail_timeout);
+    }
+  }
+}
+
+impl Upstream {
+  fn is_available(&self) -> bool {
+    self.down_until.lock().unwrap().is_none_or(|until| Instant::now() >= until)
+  }
+
+  fn forward_uri(&self, uri: &Uri) -> Uri {
+    let base = self.uri.path().trim_end_matches('/');
+    let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+    let mut parts = self.uri.clone().into_parts();
+    parts.path_and_query = format!("{base}{path_and_query}").parse().ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| self.uri.clone())
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UpstreamConfig {
+  Single(String),
+  List(Vec<String>),
+  Table {
+    servers: Vec<String>,
+    #[serde(default = "default_max_fails")]
+    max_fails: u32,
+    #[serde(default = "default_fail_timeout", deserialize_with = "deserialize_duration")]
+    fail_timeout: Duration,
+  },
+}
+
+fn default_max_fails() -> u32 {
+  1
+}
+
+fn default_fail_timeout() -> Duration {
+  Duration::from_secs(10)
+}
+
+impl TryFrom<UpstreamConfig> for Upstreams {
+  type Error = anyhow::Error;
+
+  fn try_from(config: UpstreamConfig) -> Result<Self, Self::Error> {
+    let (servers, max_fails, fail_timeout) = match config {
+      UpstreamConfig::Single(server) => (vec![server], default_max_fails(), default_fail_timeout()),
+      UpstreamConfig::List(servers) => (servers, default_max_fails(), default_fail_timeout()),
+      UpstreamConfig::Table {
+        servers,
+        max_fails,
+        fail_timeout,
+      } => (servers, max_fails, fail_timeout),
+    };
+    if servers.is_empty() {
+      anyhow::bail!("`upstream` requires at least one server");
+    }
+
+    let servers = servers
+      .into_iter()
+      .map(|server| {
+        let uri = server.parse::<Uri>()?;
+        if uri.scheme_str() != Some("http") || uri.authority().is_none() {
+          anyhow::bail!("Upstream must be an absolute http:// URI: {server}");
+        }
+        Ok(Upstream {
+          uri,
+          fails: AtomicU32::default(),
+          down_until: Mutex::default(),
+        })
+      })
+      .collect::<anyhow::Result<Vec<Upstream>>>()?;
+
+    Ok(Self(Arc::new(UpstreamPool {
+      servers,
+      next: AtomicUsize::default(),
+      max_fails: max_fails.max(1),
+      fail_timeout,
+    })))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::Uri;
+  use serde::Deserialize;
+
+  use crate::config::upstream::Upstreams;
+
+  #[derive(Deserialize)]
+  struct Action {
+    upstream: Upstreams,
+  }
+
+  fn upstreams(toml: &str) -> Upstreams {
+    toml::from_str::<Action>(toml).unwrap().upstream
+  }
+
+  #[test]
+  fn test_round_robin() {
+    let upstreams = upstreams(r#"upstream = ["http://127.0.0.1:3000", "http://127.0.0.1:3001/app/"]"#);
+    let uri = Uri::from_static("/foo?bar=baz");
+
+    assert_eq!(upstreams.select(&uri), (0, Uri::from_static("http://127.0.0.1:3000/foo?bar=baz")));
+    assert_eq!(upstreams.select(&uri), (1, Uri::from_static("http://127.0.0.1:3001/app/foo?bar=baz")));
+    assert_eq!(upstreams.select(&uri).0, 0);
+  }
+
+  #[test]
+  fn test_passive_health_check() {
+    let upstreams = upstreams(r#"upstream = { servers = ["http://127.0.0.1:3000", "http://127.0.0.1:3001"], max_fails = 2 }"#);
+    let uri = Uri::from_static("/");
+
+    upstreams.report(0, false);
+    assert_eq!(upstreams.select(&uri).0, 0);
+    upstreams.report(0, false);
+    assert_eq!(upstreams.select(&uri).0, 1);
+    assert_eq!(upstreams.select(&uri).0, 1);
+  }
+
+  #[test]
+  fn test_invalid_upstream() {
+    assert!(toml::from_str::<Action>(r#"upstream = []"#).is_err());
+    assert!(toml::from_str::<Action>(r#"upstream = "127.0.0.1:3000""#).is_err());
+    assert!(toml::from_str::<Action>(r#"upstream = "https://example.com""#).is_err());
+  }
+}
@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use pasir_sys::zend_atomic_bool;
+use pasir_sys::zend_execute_data;
+use pasir_sys::zend_string;
+
+use crate::slowlog::SLOWLOG;
+
+/// PHP executions in flight, listed by the status route and checked by the slowlog.
+pub(crate) static EXECUTIONS: LazyLock<Executions> = LazyLock::new(Executions::default);
+
+/// Frames beyond this depth are left out of a backtrace.
+const MAX_FRAMES: usize = 100;
+
+type InterruptFunction = unsafe extern "C" fn(*mut zend_execute_data);
+
+/// `zend_interrupt_function` set before `install_interrupt_function`, e.g. by `pcntl`.
+static PREVIOUS_INTERRUPT_FUNCTION: OnceLock<Option<InterruptFunction>> = OnceLock::new();
+
+thread_local! {
+  /// Execution running on the current PHP thread.
+  static CURRENT: RefCell<Option<(u64, Arc<Shared>)>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+pub(crate) struct Executions {
+  next_id: AtomicU64,
+  executions: Mutex<BTreeMap<u64, Execution>>,
+}
+
+pub(crate) struct Execution {
+  pub(crate) request_id: Option<String>,
+  pub(crate) method: String,
+  pub(crate) uri: String,
+  pub(crate) script: PathBuf,
+  pub(crate) started: Instant,
+  /// Whether the execution was reported to the slowlog already.
+  pub(crate) slow: bool,
+  shared: Arc<Shared>,
+  vm_interrupt: VmInterrupt,
+}
+
+/// State of an execution updated by the PHP thread running it.
+#[derive(Default)]
+struct Shared {
+  memory: AtomicU64,
+  backtrace_requested: AtomicBool,
+}
+
+/// `EG(vm_interrupt)` of the PHP thread running an execution.
+struct VmInterrupt(*mut zend_atomic_bool);
+
+// SAFETY: the executor globals of a PHP thread outlive the executions it tracks, and the flag is only
+// stored to, atomically, while the execution is tracked.
+unsafe impl Send for VmInterrupt {}
+
+/// Tracks an execution until dropped.
+pub(crate) struct ExecutionGuard<'a> {
+  executions: &'a Executions,
+  id: u64,
+}
+
+impl Drop for ExecutionGuard<'_> {
+  fn drop(&mut self) {
+    CURRENT.with_borrow_mut(|current| *current = None);
+    let execution = self.executions.executions.lock().unwrap().remove(&self.id);
+    // The execution ended before PHP served the backtrace request.
+    if let Some(execution) = execution
+      && execution.shared.backtrace_requested.load(Ordering::Acquire)
+      && let Some(slowlog) = SLOWLOG.get()
+    {
+      slowlog.log(&execution, &[]);
+    }
+  }
+}
+
+impl Executions {
+  /// Tracks an execution of `script`, from the PHP thread running it.
+  pub(crate) fn track(
+    &self,
+    request_id: Option<&str>,
+    method: String,
+    uri: String,
+    script: PathBuf,
+    vm_interrupt: *mut zend_atomic_bool,
+  ) -> ExecutionGuard<'_> {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let shared = Arc::new(Shared::default());
+    CURRENT.with_borrow_mut(|current| *current = Some((id, shared.clone())));
+    let execution = Execution {
+      request_id: request_id.map(str::to_string),
+      method,
+      uri,
+      script,
+      started: Instant::now(),
+      slow: false,
+      shared,
+      vm_interrupt: VmInterrupt(vm_interrupt),
+    };
+    self.executions.lock().unwrap().insert(id, execution);
+    ExecutionGuard { executions: self, id }
+  }
+
+  /// Executions in flight, by start order. They cannot end while the lock is held.
+  pub(crate) fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Execution>> {
+    self.executions.lock().unwrap()
+  }
+}
+
+impl Execution {
+  /// Memory used by PHP, as sampled by the PHP thread when the request started and whenever the script
+  /// writes output. It is stale while the script runs without printing.
+  pub(crate) fn memory(&self) -> u64 {
+    self.shared.memory.load(Ordering::Relaxed)
+  }
+
+  /// Asks the PHP thread to log the backtrace of the execution to the slowlog, which it does from
+  /// `zend_interrupt_function` once the VM runs again, e.g. after a blocking call returns.
+  pub(crate) fn request_backtrace(&self) {
+    self.shared.backtrace_requested.store(true, Ordering::Release);
+    if !self.vm_interrupt.0.is_null() {
+      unsafe { pasir_sys::zend_atomic_bool_store(self.vm_interrupt.0, true) };
+    }
+  }
+}
+
+/// Samples the memory used by the execution running on the current PHP thread.
+pub(crate) fn sample_memory() {
+  CURRENT.with_borrow(|current| {
+    if let Some((_, shared)) = current {
+      shared
+        .memory
+        .store(unsafe { pasir_sys::zend_memory_usage(false) } as u64, Ordering::Relaxed);
+    }
+  });
+}
+
+/// Installs the `zend_interrupt_function` serving the backtrace requests, keeping the previous one.
+pub(crate) fn install_interrupt_function() {
+  PREVIOUS_INTERRUPT_FUNCTION.get_or_init(|| unsafe {
+    let previous = pasir_sys::zend_interrupt_function;
+    pasir_sys::zend_interrupt_function = Some(interrupt);
+    previous
+  });
+}
+
+unsafe extern "C" fn interrupt(execute_data: *mut zend_execute_data) {
+  let requested = CURRENT.with_borrow(|current| {
+    current
+      .as_ref()
+      .filter(|(_, shared)| shared.backtrace_requested.swap(false, Ordering::AcqRel))
+      .map(|(id, _)| *id)
+  });
+  if let Some(id) = requested
+    && let Some(slowlog) = SLOWLOG.get()
+  {
+    // Frames are read from the thread executing them, so they cannot change meanwhile.
+    let backtrace = unsafe { backtrace(execute_data) };
+    if let Some(execution) = EXECUTIONS.lock().get(&id) {
+      slowlog.log(execution, &backtrace);
+    }
+  }
+
+  if let Some(previous) = PREVIOUS_INTERRUPT_FUNCTION.get().copied().flatten() {
+    unsafe { previous(execute_data) };
+  }
+}
+
+/// Reads the call stack from `execute_data`, innermost frame first. Each frame is the function and
+/// the file and line being executed, or of the caller for internal functions.
+///
+/// Must be called from the PHP thread executing the frames.
+unsafe fn backtrace(mut execute_data: *const zend_execute_data) -> Vec<String> {
+  let mut frames = vec![];
+  for _ in 0..MAX_FRAMES {
+    let Some(data) = (unsafe { execute_data.as_ref() }) else {
+      break;
+    };
+    let frame = execute_data;
+    execute_data = data.prev_execute_data;
+    let Some(function) = (unsafe { data.func.as_ref() }) else {
+      continue;
+    };
+
+    let name = unsafe { zend_string_lossy(function.common.function_name) };
+    let name = match (name, unsafe { function.common.scope.as_ref() }) {
+      (Some(name), Some(scope)) => format!("{}::{name}()", unsafe { zend_string_lossy(scope.name) }.unwrap_or_default()),
+      (Some(name), None) => format!("{name}()"),
+      (None, _) => "{main}".to_string(),
+    };
+    match unsafe { location(frame) } {
+      Some((file, line)) => frames.push(format!("{name} {file}:{line}")),
+      None => frames.push(name),
+    }
+  }
+  frames
+}
+
+/// File and line executed by the frame, or by the closest user code frame calling it.
+unsafe fn location(mut execute_data: *const zend_execute_data) -> Option<(String, u32)> {
+  for _ in 0..MAX_FRAMES {
+    let data = unsafe { execute_data.as_ref() }?;
+    if let Some(function) = unsafe { data.func.as_ref() }
+      // `ZEND_USER_CODE`: user functions and eval'd code, as opposed to internal functions.
+      && unsafe { function.type_ } & 1 == 0
+    {
+      let file = unsafe { zend_string_lossy(function.op_array.filename) }?;
+      let line = unsafe { data.opline.as_ref() }.map_or(0, |opline| opline.lineno);
+      return Some((file, line));
+    }
+    execute_data = data.prev_execute_data;
+  }
+  None
+}
+
+unsafe fn zend_string_lossy(string: *const zend_string) -> Option<String> {
+  let string = unsafe { string.as_ref() }?;
+  let bytes = unsafe { std::slice::from_raw_parts(string.val.as_ptr().cast::<u8>(), string.len) };
+  Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use crate::executions::Executions;
+
+  #[test]
+  fn test_track() {
+    let executions = Executions::default();
+    let guard = executions.track(
+      Some("f81d4fae"),
+      "GET".to_string(),
+      "/slow.php?foo=bar".to_string(),
+      PathBuf::from("/srv/www/slow.php"),
+      std::ptr::null_mut(),
+    );
+    {
+      let executions = executions.lock();
+      let execution = executions.values().next().unwrap();
+      assert_eq!(execution.request_id.as_deref(), Some("f81d4fae"));
+      assert_eq!(execution.uri, "/slow.php?foo=bar");
+      assert_eq!(execution.memory(), 0);
+      execution.request_backtrace();
+    }
+
+    drop(guard);
+    assert!(executions.lock().is_empty());
+  }
+}
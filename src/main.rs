@@ -1,29 +1,79 @@
 #![cfg_attr(coverage_nightly, feature(coverage_attribute))]
 
+mod access_log;
 mod cli;
 mod config;
+mod executions;
+mod metrics;
+mod otel;
 mod sapi;
 mod service;
+mod slowlog;
+mod status;
 mod util;
 
+use std::fs::OpenOptions;
+use std::sync::Mutex;
+
 use clap::Parser;
+use opentelemetry::trace::TracerProvider as _;
 use tracing::error;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 use crate::cli::Cli;
 use crate::cli::Executable;
+use crate::cli::LogFormat;
 
 #[tokio::main]
 async fn main() {
   let cli = Cli::parse();
 
-  let format = tracing_subscriber::fmt::format().compact();
-  tracing_subscriber::fmt()
-    .event_format(format)
-    .with_max_level(cli.verbosity())
+  let writer = match cli.log_file() {
+    Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+      Ok(file) => BoxMakeWriter::new(Mutex::new(file)),
+      Err(err) => {
+        eprintln!("Failed to open log file {}: {err}", path.display());
+        std::process::exit(1);
+      }
+    },
+    None => BoxMakeWriter::new(std::io::stderr),
+  };
+  let fmt_layer = tracing_subscriber::fmt::layer()
     .with_target(false)
+    .with_ansi(cli.log_file().is_none())
+    .with_writer(writer);
+  let fmt_layer = match cli.log_format() {
+    LogFormat::Json => fmt_layer.json().flatten_event(true).boxed(),
+    LogFormat::Compact => fmt_layer.compact().boxed(),
+    LogFormat::Pretty => fmt_layer.pretty().boxed(),
+  };
+  let tracer_provider = match cli.otlp_endpoint().map(otel::tracer_provider).transpose() {
+    Ok(tracer_provider) => tracer_provider,
+    Err(err) => {
+      eprintln!("Failed to set up the OTLP exporter: {err}");
+      std::process::exit(1);
+    }
+  };
+  // Spans are exported down to the debug level, independently of the verbosity.
+  let otel_layer = tracer_provider.as_ref().map(|provider| {
+    tracing_opentelemetry::layer()
+      .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+      .with_filter(LevelFilter::DEBUG)
+  });
+  tracing_subscriber::registry()
+    .with(fmt_layer.with_filter(cli.verbosity().tracing_level_filter()))
+    .with(otel_layer)
     .init();
 
-  if let Err(err) = cli.execute().await {
+  let result = cli.execute().await;
+  if let Some(provider) = tracer_provider {
+    let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+  }
+  if let Err(err) = result {
     error!("{}", err);
     std::process::exit(1);
   }
@@ -0,0 +1,345 @@
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::body::Incoming;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::runtime::Handle;
+use tracing::debug;
+use tracing::error;
+
+/// Process wide metrics, exposed in the Prometheus text format with `--metrics-listen`.
+pub(crate) static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds in seconds of the request duration histogram buckets.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+thread_local! {
+  /// Counts the blocking thread in `php_threads` once it has run PHP, until it exits.
+  static PHP_THREAD: OnceCell<GaugeGuard<'static>> = const { OnceCell::new() };
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+  requests: Mutex<BTreeMap<(String, u16), Histogram>>,
+  connections: AtomicU64,
+  accepted_connections: AtomicU64,
+  php_threads: AtomicU64,
+  php_queued: AtomicU64,
+  php_max_queued: AtomicU64,
+  php_active: AtomicU64,
+  php_max_active: AtomicU64,
+  php_bailouts: AtomicU64,
+  slow_requests: AtomicU64,
+  timeouts: AtomicU64,
+  received_bytes: AtomicU64,
+  sent_bytes: AtomicU64,
+}
+
+/// Point in time values of the counters and gauges shown on the status route.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Snapshot {
+  pub(crate) accepted_connections: u64,
+  pub(crate) php_threads: u64,
+  pub(crate) php_queued: u64,
+  pub(crate) php_max_queued: u64,
+  pub(crate) php_active: u64,
+  pub(crate) php_max_active: u64,
+  pub(crate) slow_requests: u64,
+}
+
+#[derive(Default)]
+struct Histogram {
+  buckets: [u64; BUCKETS.len()],
+  sum: f64,
+  count: u64,
+}
+
+/// Increments a gauge until dropped.
+pub(crate) struct GaugeGuard<'a>(&'a AtomicU64);
+
+impl<'a> GaugeGuard<'a> {
+  fn new(gauge: &'a AtomicU64) -> Self {
+    gauge.fetch_add(1, Ordering::Relaxed);
+    Self(gauge)
+  }
+
+  /// Also records the highest value reached by the gauge in `max`.
+  fn with_max(gauge: &'a AtomicU64, max: &AtomicU64) -> Self {
+    let value = gauge.fetch_add(1, Ordering::Relaxed) + 1;
+    max.fetch_max(value, Ordering::Relaxed);
+    Self(gauge)
+  }
+}
+
+impl Drop for GaugeGuard<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+impl Metrics {
+  /// Records a served request under the index of the route that served it.
+  pub(crate) fn observe_request(&self, route: Option<usize>, status: StatusCode, duration: Duration) {
+    let route = route.map_or_else(|| "fallback".to_string(), |index| index.to_string());
+    let mut requests = self.requests.lock().unwrap();
+    let histogram = requests.entry((route, status.as_u16())).or_default();
+    let seconds = duration.as_secs_f64();
+    if let Some(index) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+      histogram.buckets[index] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+  }
+
+  pub(crate) fn connection(&self) -> GaugeGuard<'_> {
+    self.accepted_connections.fetch_add(1, Ordering::Relaxed);
+    GaugeGuard::new(&self.connections)
+  }
+
+  /// Counts the current thread as a PHP thread until it exits.
+  pub(crate) fn php_thread(&'static self) {
+    PHP_THREAD.with(|thread| {
+      thread.get_or_init(|| GaugeGuard::new(&self.php_threads));
+    });
+  }
+
+  /// Tracks a PHP execution waiting for a thread of the blocking pool.
+  pub(crate) fn php_queued(&self) -> GaugeGuard<'_> {
+    GaugeGuard::with_max(&self.php_queued, &self.php_max_queued)
+  }
+
+  pub(crate) fn php_active(&self) -> GaugeGuard<'_> {
+    GaugeGuard::with_max(&self.php_active, &self.php_max_active)
+  }
+
+  pub(crate) fn php_bailout(&self) {
+    self.php_bailouts.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn slow_request(&self) {
+    self.slow_requests.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn timeout(&self) {
+    self.timeouts.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn received(&self, bytes: usize) {
+    self.received_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+  }
+
+  pub(crate) fn sent(&self, bytes: usize) {
+    self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+  }
+
+  pub(crate) fn snapshot(&self) -> Snapshot {
+    let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+    Snapshot {
+      accepted_connections: load(&self.accepted_connections),
+      php_threads: load(&self.php_threads),
+      php_queued: load(&self.php_queued),
+      php_max_queued: load(&self.php_max_queued),
+      php_active: load(&self.php_active),
+      php_max_active: load(&self.php_max_active),
+      slow_requests: load(&self.slow_requests),
+    }
+  }
+
+  /// Renders the metrics in the Prometheus text exposition format.
+  pub(crate) fn render(&self) -> String {
+    let mut output = String::new();
+    let requests = self.requests.lock().unwrap();
+
+    header(&mut output, "pasir_http_requests_total", "counter", "HTTP requests by route and status.");
+    for ((route, status), histogram) in requests.iter() {
+      let _ = writeln!(output, "pasir_http_requests_total{{route=\"{route}\",status=\"{status}\"}} {}", histogram.count);
+    }
+
+    let name = "pasir_http_request_duration_seconds";
+    header(&mut output, name, "histogram", "Time to the response head by route and status.");
+    for ((route, status), histogram) in requests.iter() {
+      let labels = format!("route=\"{route}\",status=\"{status}\"");
+      let mut cumulative = 0;
+      for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+        cumulative += count;
+        let _ = writeln!(output, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+      }
+      let _ = writeln!(output, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", histogram.count);
+      let _ = writeln!(output, "{name}_sum{{{labels}}} {}", histogram.sum);
+      let _ = writeln!(output, "{name}_count{{{labels}}} {}", histogram.count);
+    }
+    drop(requests);
+
+    let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+    let metrics = [
+      ("pasir_open_connections", "gauge", "Open client connections.", load(&self.connections)),
+      (
+        "pasir_accepted_connections_total",
+        "counter",
+        "Client connections accepted.",
+        load(&self.accepted_connections),
+      ),
+      ("pasir_php_threads", "gauge", "Blocking threads that have run PHP.", load(&self.php_threads)),
+      (
+        "pasir_php_executions_queued",
+        "gauge",
+        "PHP executions waiting for a blocking thread.",
+        load(&self.php_queued),
+      ),
+      ("pasir_php_executions_active", "gauge", "PHP executions running.", load(&self.php_active)),
+      (
+        "pasir_php_bailouts_total",
+        "counter",
+        "PHP executions aborted by a fatal error.",
+        load(&self.php_bailouts),
+      ),
+      (
+        "pasir_slow_requests_total",
+        "counter",
+        "PHP executions that exceeded the slowlog timeout.",
+        load(&self.slow_requests),
+      ),
+      (
+        "pasir_timeouts_total",
+        "counter",
+        "Requests that exceeded max_execution_time.",
+        load(&self.timeouts),
+      ),
+      ("pasir_received_bytes_total", "counter", "Request body bytes received.", load(&self.received_bytes)),
+      ("pasir_sent_bytes_total", "counter", "Response body bytes sent.", load(&self.sent_bytes)),
+    ];
+    for (name, kind, help, value) in metrics {
+      header(&mut output, name, kind, help);
+      let _ = writeln!(output, "{name} {value}");
+    }
+
+    if let Ok(handle) = Handle::try_current() {
+      let runtime = handle.metrics();
+      let metrics = [
+        ("pasir_runtime_workers", "Worker threads of the async runtime.", runtime.num_workers()),
+        ("pasir_runtime_alive_tasks", "Alive tasks of the async runtime.", runtime.num_alive_tasks()),
+        (
+          "pasir_runtime_global_queue_depth",
+          "Tasks in the global queue of the async runtime.",
+          runtime.global_queue_depth(),
+        ),
+      ];
+      for (name, help, value) in metrics {
+        header(&mut output, name, "gauge", help);
+        let _ = writeln!(output, "{name} {value}");
+      }
+    }
+
+    output
+  }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(output, "# HELP {name} {help}");
+  let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+/// Serves `GET /metrics` on the listener until the process exits.
+pub(crate) async fn serve(listener: TcpListener) {
+  loop {
+    let stream = match listener.accept().await {
+      Ok((stream, _)) => stream,
+      Err(e) => {
+        error!("Failed to accept metrics connection: {e}");
+        continue;
+      }
+    };
+    tokio::spawn(async move {
+      let service = service_fn(|request: Request<Incoming>| async move { Ok::<_, Infallible>(respond(&request)) });
+      if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+        debug!("Error serving metrics connection: {e}");
+      }
+    });
+  }
+}
+
+fn respond<B>(request: &Request<B>) -> Response<Full<Bytes>> {
+  if request.uri().path() != "/metrics" || !matches!(*request.method(), Method::GET | Method::HEAD) {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    return response;
+  }
+
+  let mut response = Response::new(Full::from(METRICS.render()));
+  response
+    .headers_mut()
+    .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"));
+  response
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use hyper::Request;
+  use hyper::StatusCode;
+
+  use crate::metrics::Metrics;
+  use crate::metrics::respond;
+
+  #[test]
+  fn test_render() {
+    let metrics = Metrics::default();
+    metrics.observe_request(Some(0), StatusCode::OK, Duration::from_millis(20));
+    metrics.observe_request(Some(0), StatusCode::OK, Duration::from_secs(20));
+    metrics.observe_request(None, StatusCode::NOT_FOUND, Duration::from_millis(1));
+    metrics.php_bailout();
+    metrics.sent(42);
+    let _connection = metrics.connection();
+    {
+      let _active = metrics.php_active();
+    }
+
+    let output = metrics.render();
+    assert!(output.contains("# TYPE pasir_http_requests_total counter\n"));
+    assert!(output.contains("pasir_http_requests_total{route=\"0\",status=\"200\"} 2\n"));
+    assert!(output.contains("pasir_http_requests_total{route=\"fallback\",status=\"404\"} 1\n"));
+    assert!(output.contains("pasir_http_request_duration_seconds_bucket{route=\"0\",status=\"200\",le=\"0.01\"} 0\n"));
+    assert!(output.contains("pasir_http_request_duration_seconds_bucket{route=\"0\",status=\"200\",le=\"0.025\"} 1\n"));
+    assert!(output.contains("pasir_http_request_duration_seconds_bucket{route=\"0\",status=\"200\",le=\"10\"} 1\n"));
+    assert!(output.contains("pasir_http_request_duration_seconds_bucket{route=\"0\",status=\"200\",le=\"+Inf\"} 2\n"));
+    assert!(output.contains("pasir_http_request_duration_seconds_count{route=\"0\",status=\"200\"} 2\n"));
+    assert!(output.contains("pasir_php_bailouts_total 1\n"));
+    assert!(output.contains("pasir_sent_bytes_total 42\n"));
+    assert!(output.contains("pasir_open_connections 1\n"));
+    assert!(output.contains("pasir_php_executions_active 0\n"));
+    assert!(output.contains("pasir_accepted_connections_total 1\n"));
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.php_active, 0);
+    assert_eq!(snapshot.php_max_active, 1);
+  }
+
+  #[test]
+  fn test_respond() {
+    let response = respond(&Request::builder().uri("/metrics").body(()).unwrap());
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "text/plain; version=0.0.4; charset=utf-8");
+
+    let response = respond(&Request::builder().uri("/").body(()).unwrap());
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use hyper::HeaderMap;
+use hyper::Request;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Span;
+use tracing::debug_span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Exports spans over gRPC to the OTLP collector at `endpoint`, enabled with `--otlp-endpoint`.
+///
+/// The service name is `pasir`, unless set with `OTEL_SERVICE_NAME`. Must be called from the Tokio runtime.
+pub(crate) fn tracer_provider(endpoint: &str) -> anyhow::Result<SdkTracerProvider> {
+  let exporter = SpanExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+  let mut resource = Resource::builder();
+  if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+    resource = resource.with_service_name(env!("CARGO_PKG_NAME"));
+  }
+
+  Ok(
+    SdkTracerProvider::builder()
+      .with_batch_exporter(exporter)
+      .with_resource(resource.build())
+      .build(),
+  )
+}
+
+/// Span of a request, continuing the trace of its `traceparent` header, if any.
+pub(crate) fn make_span<B>(request: &Request<B>) -> Span {
+  let span = debug_span!(
+    parent: None,
+    "request",
+    method = %request.method(),
+    uri = %request.uri(),
+    version = ?request.version(),
+    otel.kind = "server",
+  );
+  let parent = TraceContextPropagator::new().extract(&HeaderExtractor(request.headers()));
+  let _ = span.set_parent(parent);
+  span
+}
+
+/// `TRACEPARENT` and `TRACESTATE` of the current span, for PHP to create child spans. Empty when the
+/// span is not exported.
+pub(crate) fn trace_context() -> HashMap<String, String> {
+  let mut carrier = HashMap::new();
+  TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+  carrier
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .map(|(name, value)| (name.to_uppercase(), value))
+    .collect()
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+  fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).and_then(|value| value.to_str().ok())
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(|name| name.as_str()).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::Bytes;
+  use http_body_util::Empty;
+  use hyper::Request;
+  use opentelemetry::trace::TraceContextExt;
+  use opentelemetry_sdk::trace::SdkTracerProvider;
+  use tracing::Instrument;
+  use tracing_opentelemetry::OpenTelemetrySpanExt;
+  use tracing_subscriber::layer::SubscriberExt;
+
+  use crate::otel::make_span;
+  use crate::otel::trace_context;
+
+  #[tokio::test]
+  async fn test_trace_context() {
+    let provider = SdkTracerProvider::builder().build();
+    let tracer = opentelemetry::trace::TracerProvider::tracer(&provider, "test");
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let request = Request::builder()
+      .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+      .body(Empty::<Bytes>::default())
+      .unwrap();
+    let span = make_span(&request);
+    let span_context = span.context().span().span_context().clone();
+    assert_eq!(span_context.trace_id().to_string(), "0af7651916cd43dd8448eb211c80319c");
+
+    let context = async { trace_context() }.instrument(span).await;
+    let traceparent = &context["TRACEPARENT"];
+    assert!(traceparent.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+    assert_eq!(traceparent, &format!("00-0af7651916cd43dd8448eb211c80319c-{}-01", span_context.span_id()));
+  }
+
+  #[test]
+  fn test_trace_context_without_span() {
+    assert!(trace_context().is_empty());
+  }
+}
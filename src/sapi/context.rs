@@ -1,11 +1,20 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ffi::CString;
 use std::ops::Sub;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 use ext_php_rs::embed::RequestInfo;
 use ext_php_rs::embed::ServerVarRegistrar;
+use ext_php_rs::ffi::zend_ini_entry;
+use ext_php_rs::types::ZendStr;
+use ext_php_rs::zend::ExecutorGlobals;
 use ext_php_rs::zend::SapiGlobals;
 use headers::Authorization;
 use headers::ContentLength;
@@ -21,15 +30,22 @@ use hyper::Uri;
 use hyper::Version;
 use hyper::body::Frame;
 use hyper::header::IntoHeaderName;
+use hyper::http::HeaderName;
 use hyper::http::HeaderValue;
 use hyper::http::response::Parts;
 use pasir::unbound_channel::Sender;
 use pasir::unbound_channel::UnboundChannel;
+use pasir_sapi::context::RequestTimings;
 use pasir_sapi::context::ServerContext;
+use pasir_sys::ZEND_INI_PERDIR;
+use pasir_sys::ZEND_INI_STAGE_ACTIVATE;
+use pasir_sys::ZEND_INI_SYSTEM;
+use pasir_sys::ZEND_RESULT_CODE_FAILURE;
 use tokio::sync::oneshot::Receiver;
 use tokio::sync::oneshot::Sender as OneShotSender;
 use tracing::debug;
 use tracing::instrument;
+use tracing::warn;
 
 use crate::cli::serve::Stream;
 use crate::sapi::ext::FromSapiHeaders;
@@ -41,6 +57,63 @@ pub(crate) enum ResponseType {
   Chunked,
 }
 
+/// Script resolved ahead of PHP execution, e.g. by a `try_files` route, bypassing `Context::parse_uri`.
+#[derive(Clone, Debug)]
+pub(crate) struct ScriptName(pub(crate) String);
+
+/// Extra `$_SERVER` and environment variables of the request, collected from `pasir.toml`.
+///
+/// Environment variables are served through `getenv()` and registered in `$_SERVER` as well.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestVariables {
+  pub(crate) server: HashMap<String, String>,
+  pub(crate) env: HashMap<String, String>,
+}
+
+pub(crate) static SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Requests a `Server-Timing` header for the response, enabled by `server_timing` in `pasir.toml`.
+///
+/// The output flush is recorded by the context when the request finishes. Only buffered responses get the
+/// header: the head of a streamed response is sent when the script flushes, before the timings are known.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServerTiming {
+  flush: Arc<OnceLock<Duration>>,
+}
+
+impl ServerTiming {
+  /// Header value of the phases of the request, in milliseconds. The output flush happens within the
+  /// request shutdown and is left out of its duration.
+  pub(crate) fn header_value(&self, queue: Duration, timings: RequestTimings) -> HeaderValue {
+    let flush = self.flush.get().copied().unwrap_or_default();
+    let metrics = [
+      ("queue", queue),
+      ("startup", timings.startup),
+      ("script", timings.execute),
+      ("flush", flush),
+      ("shutdown", timings.shutdown.saturating_sub(flush)),
+    ];
+    let value = metrics
+      .iter()
+      .map(|(name, duration)| format!("{name};dur={:.3}", duration.as_secs_f64() * 1000.0))
+      .collect::<Vec<_>>()
+      .join(", ");
+    HeaderValue::from_str(&value).unwrap()
+  }
+}
+
+/// PHP INI entries of the request, collected from `pasir.toml`, similar to FPM's `php_value` and
+/// `php_admin_value`.
+///
+/// They are altered when the request is activated and restored by PHP at request shutdown. Admin values
+/// cannot be changed by the script with `ini_set()`. Entries PHP only reads at startup, such as
+/// `disable_functions`, are accepted but have no effect.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IniEntries {
+  pub(crate) values: HashMap<String, String>,
+  pub(crate) admin_values: HashMap<String, String>,
+}
+
 #[derive(Debug, Default)]
 pub struct Context {
   root: Arc<PathBuf>,
@@ -48,25 +121,41 @@ pub struct Context {
   path_info: Option<String>,
   stream: Arc<Stream>,
   request: Request<Bytes>,
+  variables: RequestVariables,
+  /// Environment variables as NUL-terminated strings, borrowed by PHP from `getenv()`.
+  env: HashMap<String, CString>,
+  ini: IniEntries,
   headers: HeaderMap,
   sender: ContextSender,
+  server_timing: Option<ServerTiming>,
   request_finished: bool,
 }
 
 impl Context {
   pub(crate) fn new(root: Arc<PathBuf>, stream: Arc<Stream>, request: Request<Bytes>, sender: ContextSender) -> Self {
     let uri = request.uri().path().to_string();
+    let script_name = request.extensions().get::<ScriptName>().cloned();
+    let variables = request.extensions().get::<RequestVariables>().cloned().unwrap_or_default();
+    let ini = request.extensions().get::<IniEntries>().cloned().unwrap_or_default();
+    let server_timing = request.extensions().get::<ServerTiming>().cloned();
     let mut context = Self {
       root,
       script_name: Default::default(),
       path_info: None,
       stream,
       request,
+      env: c_env(&variables),
+      variables,
+      ini,
       sender,
       headers: Default::default(),
+      server_timing,
       request_finished: false,
     };
-    context.parse_uri(uri, None);
+    match script_name {
+      Some(ScriptName(script_name)) => context.script_name = script_name,
+      None => context.parse_uri(uri, None),
+    }
     context
   }
 
@@ -113,10 +202,42 @@ impl Context {
     self.path_info.as_deref()
   }
 
+  /// `X-Request-Id` of the request, attached to the messages PHP logs while serving it.
+  pub(crate) fn request_id(&self) -> Option<&str> {
+    self.request.headers().get("x-request-id").and_then(|value| value.to_str().ok())
+  }
+
   pub(crate) fn headers(&self) -> &HeaderMap {
     self.request.headers()
   }
 
+  pub(crate) fn env(&self, name: &str) -> Option<&CStr> {
+    self.env.get(name).map(CString::as_c_str)
+  }
+
+  #[instrument(skip(self))]
+  pub(crate) fn alter_ini_entries(&self) {
+    let values = self.ini.values.iter().map(|entry| (entry, ZEND_INI_PERDIR));
+    let admin_values = self.ini.admin_values.iter().map(|entry| (entry, ZEND_INI_SYSTEM));
+    for ((name, value), modify_type) in values.chain(admin_values) {
+      let mut zend_name = ZendStr::new(name, false);
+      let result = unsafe {
+        pasir_sys::zend_alter_ini_entry_chars(
+          &mut *zend_name,
+          value.as_ptr().cast(),
+          value.len(),
+          modify_type.cast_signed(),
+          ZEND_INI_STAGE_ACTIVATE.cast_signed(),
+        )
+      };
+      if result == ZEND_RESULT_CODE_FAILURE {
+        warn!("Failed to set INI entry {name}={value}");
+      } else if modify_type == ZEND_INI_SYSTEM {
+        lock_ini_entry(name);
+      }
+    }
+  }
+
   pub(crate) fn append_response_header<K>(&mut self, key: K, value: HeaderValue)
   where
     K: IntoHeaderName,
@@ -124,7 +245,7 @@ impl Context {
     self.headers.append(key, value);
   }
 
-  #[instrument(skip(self, data))]
+  #[instrument(level = "trace", skip(self, data))]
   pub(crate) fn ub_write(&mut self, data: Bytes) -> bool {
     if let Some(mut body_tx) = self.sender.body.take() {
       if let Err(frame) = body_tx.send(Frame::data(data)) {
@@ -139,7 +260,7 @@ impl Context {
     false
   }
 
-  #[instrument(skip(self))]
+  #[instrument(level = "trace", skip(self))]
   pub(crate) fn flush(&mut self) -> bool {
     if self.sender.head.is_some() {
       let (mut parts, _) = Response::<Bytes>::default().into_parts();
@@ -215,7 +336,11 @@ impl ext_php_rs::embed::ServerContext for Context {
       return false;
     }
 
+    let started = Instant::now();
     unsafe { pasir_sys::php_output_end_all() }
+    if let Some(server_timing) = &self.server_timing {
+      let _ = server_timing.flush.set(started.elapsed());
+    }
 
     if let Some(body_tx) = self.sender.body.take() {
       body_tx.abort();
@@ -280,6 +405,10 @@ impl ServerContext for Context {
       let header_name = format!("HTTP_{}", name.as_str().to_uppercase().replace('-', "_"));
       registrar.register(&header_name, value.to_str().unwrap_or_default());
     }
+
+    for (name, value) in self.variables.env.iter().chain(self.variables.server.iter()) {
+      registrar.register(name, value);
+    }
   }
 }
 
@@ -309,6 +438,17 @@ impl ContextBuilder {
     self
   }
 
+  pub fn ini(mut self, ini: IniEntries) -> Self {
+    self.0.ini = ini;
+    self
+  }
+
+  pub fn variables(mut self, variables: RequestVariables) -> Self {
+    self.0.env = c_env(&variables);
+    self.0.variables = variables;
+    self
+  }
+
   pub fn sender(mut self, sender: ContextSender) -> Self {
     self.0.sender = sender;
     self
@@ -319,6 +459,27 @@ impl ContextBuilder {
   }
 }
 
+/// Values containing a NUL byte cannot be returned to PHP and are left out.
+fn c_env(variables: &RequestVariables) -> HashMap<String, CString> {
+  variables
+    .env
+    .iter()
+    .filter_map(|(name, value)| Some((name.clone(), CString::new(value.as_str()).ok()?)))
+    .collect()
+}
+
+/// Keeps the script from changing an admin entry with `ini_set()`, like FPM's `php_admin_value`. PHP
+/// restores the permissions of the entry along with its value at request shutdown.
+fn lock_ini_entry(name: &str) {
+  let executor_globals = ExecutorGlobals::get();
+  let Some(ini_directives) = (unsafe { executor_globals.ini_directives.as_ref() }) else {
+    return;
+  };
+  if let Some(entry) = ini_directives.get(name).and_then(|entry| entry.ptr::<zend_ini_entry>()) {
+    unsafe { (*entry).modifiable = ZEND_INI_SYSTEM as u8 };
+  }
+}
+
 type ContextReceiver = (Receiver<Parts>, UnboundChannel<Bytes>, ContextSender);
 
 #[derive(Default, Debug)]
@@ -338,7 +499,7 @@ impl ContextSender {
     (head_rx, body_rx, sender)
   }
 
-  #[instrument(skip(self))]
+  #[instrument(level = "trace", skip(self))]
   pub(crate) fn send_head(&mut self, mut headers: Parts) -> bool {
     if let Some(head_tx) = self.head.take() {
       if let Ok(status) = StatusCode::from_sapi_headers(SapiGlobals::get().sapi_headers()) {
@@ -361,6 +522,7 @@ mod tests {
   use std::net::Ipv4Addr;
   use std::path::PathBuf;
   use std::sync::Arc;
+  use std::time::Duration;
 
   use bytes::Bytes;
   use ext_php_rs::embed::RequestInfo;
@@ -375,12 +537,16 @@ mod tests {
   use hyper::header::AUTHORIZATION;
   use hyper::header::CONTENT_LENGTH;
   use hyper::header::CONTENT_TYPE;
+  use pasir_sapi::context::RequestTimings;
   use pasir_sapi::context::ServerContext;
   use pasir_sys::ZEND_RESULT_CODE_SUCCESS;
 
   use crate::sapi::context::Context;
   use crate::sapi::context::ContextBuilder;
   use crate::sapi::context::ContextSender;
+  use crate::sapi::context::RequestVariables;
+  use crate::sapi::context::ScriptName;
+  use crate::sapi::context::ServerTiming;
   use crate::sapi::tests::SapiTestGuard;
 
   #[rstest::rstest]
@@ -404,6 +570,30 @@ mod tests {
     assert_eq!(context.path_info(), path_info);
   }
 
+  #[test]
+  fn test_script_name_extension() {
+    let root = PathBuf::from("tests/fixtures/root");
+    let request = Request::builder()
+      .uri("/foo/bar")
+      .extension(ScriptName("/foo/foo.php".to_string()))
+      .body(Bytes::default())
+      .unwrap();
+
+    let context = Context::new(Arc::new(root), Default::default(), request, Default::default());
+    assert_eq!(context.script_name(), "/foo/foo.php");
+    assert_eq!(context.path_info(), None);
+  }
+
+  #[test]
+  fn test_request_id() {
+    let request = Request::builder()
+      .header("X-Request-Id", "f81d4fae")
+      .body(Bytes::default())
+      .unwrap();
+    assert_eq!(ContextBuilder::default().request(request).build().request_id(), Some("f81d4fae"));
+    assert_eq!(ContextBuilder::default().build().request_id(), None);
+  }
+
   #[test]
   fn test_flush() {
     let _guard = SapiTestGuard::new();
@@ -488,11 +678,16 @@ mod tests {
       .uri(Uri::builder().path_and_query("/foo/bar?foo=bar").build().unwrap())
       .body(Bytes::default())
       .unwrap();
+    let variables = RequestVariables {
+      server: HashMap::from([("HTTPS".to_string(), "on".to_string())]),
+      env: HashMap::from([("APP_ENV".to_string(), "prod".to_string())]),
+    };
     let context = ContextBuilder::default()
       .root(root)
       .script_name("/index.php")
       .path_info("/foo/bar")
       .request(request)
+      .variables(variables)
       .build();
 
     assert_eq!(unsafe { pasir_sys::php_request_startup() }, ZEND_RESULT_CODE_SUCCESS);
@@ -523,5 +718,21 @@ mod tests {
     assert_eq!(vars.get("SERVER_NAME").map(|var| var.string()), Some(Some(localhost.to_string())));
     assert_eq!(vars.get("HTTP_COOKIE").map(|var| var.str()), Some(Some("foo=bar")));
     assert_eq!(vars.get("HTTP_HOST").map(|var| var.string()), Some(Some(localhost.to_string())));
+    assert_eq!(vars.get("HTTPS").map(|var| var.str()), Some(Some("on")));
+    assert_eq!(vars.get("APP_ENV").map(|var| var.str()), Some(Some("prod")));
+  }
+
+  #[test]
+  fn test_server_timing() {
+    let server_timing = ServerTiming::default();
+    let _ = server_timing.flush.set(Duration::from_micros(250));
+    let timings = RequestTimings {
+      startup: Duration::from_micros(1500),
+      execute: Duration::from_millis(12),
+      shutdown: Duration::from_millis(1),
+    };
+
+    let value = server_timing.header_value(Duration::from_micros(42), timings);
+    assert_eq!(value, "queue;dur=0.042, startup;dur=1.500, script;dur=12.000, flush;dur=0.250, shutdown;dur=0.750");
   }
 }
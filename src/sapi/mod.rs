@@ -1,6 +1,7 @@
 pub(crate) mod context;
 mod ext;
 
+use std::ffi::c_char;
 use std::str::FromStr;
 
 use bytes::Bytes;
@@ -14,6 +15,8 @@ use ext_php_rs::zend::SapiGlobals;
 use hyper::header::HeaderName;
 use hyper::header::HeaderValue;
 use pasir_sapi::context::ServerContext;
+use pasir_sys::ZEND_RESULT_CODE;
+use pasir_sys::ZEND_RESULT_CODE_SUCCESS;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
@@ -21,8 +24,40 @@ use tracing::warn;
 
 use crate::sapi::context::Context;
 
+/// Names of the syslog levels PHP logs messages with, from `LOG_EMERG` to `LOG_DEBUG`.
+const SYSLOG_LEVELS: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
 pub struct Sapi;
 
+impl Sapi {
+  extern "C" fn activate() -> ZEND_RESULT_CODE {
+    let server_context = SapiGlobals::get().server_context;
+    if !server_context.is_null() {
+      Context::from_server_context(server_context).alter_ini_entries();
+    }
+    crate::executions::sample_memory();
+
+    ZEND_RESULT_CODE_SUCCESS
+  }
+
+  /// Serves `getenv()` from the environment variables configured for the current request. PHP falls
+  /// back to the process environment when this returns null.
+  ///
+  /// PHP copies the returned string with `estrdup`, so it stays owned by the context.
+  unsafe extern "C" fn getenv(name: *const c_char, name_len: usize) -> *mut c_char {
+    let server_context = SapiGlobals::get().server_context;
+    if server_context.is_null() || name.is_null() {
+      return std::ptr::null_mut();
+    }
+
+    let name = unsafe { std::slice::from_raw_parts(name.cast::<u8>(), name_len) };
+    str::from_utf8(name)
+      .ok()
+      .and_then(|name| Context::from_server_context(server_context).env(name))
+      .map_or(std::ptr::null_mut(), |value| value.as_ptr().cast_mut())
+  }
+}
+
 impl ext_php_rs::embed::Sapi for Sapi {
   type Context = Context;
 
@@ -38,6 +73,7 @@ impl ext_php_rs::embed::Sapi for Sapi {
     if ctx.is_request_finished() {
       return 0;
     }
+    crate::executions::sample_memory();
 
     match ctx.ub_write(Bytes::copy_from_slice(buf)) {
       true => buf.len(),
@@ -49,16 +85,24 @@ impl ext_php_rs::embed::Sapi for Sapi {
   }
 
   fn log_message(message: &str, syslog_type: i32) {
+    let server_context = SapiGlobals::get().server_context;
+    let context = (!server_context.is_null()).then(|| &*Context::from_server_context(server_context));
+    let request_id = context.and_then(Context::request_id);
+    let script = context.map(Context::script_name);
+    let Some(syslog_level) = usize::try_from(syslog_type).ok().and_then(|level| SYSLOG_LEVELS.get(level)) else {
+      return;
+    };
+
     match syslog_type {
-      0..=3 => error!("{message}"),
-      4 => warn!("{message}"),
-      5 | 6 => info!("{message}"),
-      7 => debug!("{message}"),
-      _ => (),
+      0..=3 => error!(request_id, script, syslog_level, "{message}"),
+      4 => warn!(request_id, script, syslog_level, "{message}"),
+      5 | 6 => info!(request_id, script, syslog_level, "{message}"),
+      _ => debug!(request_id, script, syslog_level, "{message}"),
     };
   }
 
   fn flush(ctx: &mut Self::Context) {
+    crate::executions::sample_memory();
     ctx.flush();
   }
 
@@ -90,7 +134,9 @@ impl pasir_sapi::Sapi for Sapi {
 
     sapi_module.startup = Some(Self::startup);
     sapi_module.shutdown = Some(Self::shutdown);
+    sapi_module.activate = Some(Self::activate);
     sapi_module.deactivate = Some(Self::deactivate);
+    sapi_module.getenv = Some(Self::getenv);
     sapi_module.sapi_error = Some(pasir_sys::zend_error);
     sapi_module.phpinfo_as_text = Self::php_info_as_text().into();
 
@@ -112,16 +158,29 @@ fn pasir_finish_request() -> bool {
 
 #[cfg(test)]
 mod tests {
+  use std::collections::HashMap;
+  use std::ffi::CStr;
+
+  use bytes::Bytes;
   use ext_php_rs::embed::Sapi as _;
   use ext_php_rs::embed::ServerContext as _;
+  use ext_php_rs::types::ZendStr;
+  use ext_php_rs::zend::ExecutorGlobals;
   use ext_php_rs::zend::SapiGlobals;
+  use hyper::Request;
   use pasir_sapi::context::ServerContext;
+  use pasir_sys::ZEND_INI_STAGE_RUNTIME;
+  use pasir_sys::ZEND_INI_USER;
+  use pasir_sys::ZEND_RESULT_CODE_FAILURE;
+  use pasir_sys::ZEND_RESULT_CODE_SUCCESS;
   use tracing_test::traced_test;
 
   use crate::sapi::Sapi;
   use crate::sapi::context::Context;
   use crate::sapi::context::ContextBuilder;
   use crate::sapi::context::ContextSender;
+  use crate::sapi::context::IniEntries;
+  use crate::sapi::context::RequestVariables;
 
   pub(crate) struct SapiTestGuard {}
 
@@ -161,6 +220,8 @@ mod tests {
   #[test]
   #[traced_test]
   fn test_log_message() {
+    let _guard = SapiTestGuard::new();
+
     Sapi::log_message("foo", 8);
     assert!(!logs_contain("foo"));
 
@@ -169,6 +230,29 @@ mod tests {
       Sapi::log_message(message, syslog_type);
       assert!(logs_contain(message));
     }
+    assert!(logs_contain("syslog_level=\"err\""));
+    assert!(logs_contain("syslog_level=\"debug\""));
+  }
+
+  #[test]
+  #[traced_test]
+  fn test_log_message_request_fields() {
+    let _guard = SapiTestGuard::new();
+
+    let request = Request::builder()
+      .header("X-Request-Id", "f81d4fae")
+      .body(Bytes::default())
+      .unwrap();
+    let context = ContextBuilder::default().request(request).script_name("/index.php").build();
+    SapiGlobals::get_mut().server_context = context.into_raw().cast();
+
+    Sapi::log_message("PHP Warning:  Undefined variable $foo", 4);
+    assert!(logs_contain("request_id=\"f81d4fae\""));
+    assert!(logs_contain("script=\"/index.php\""));
+    assert!(logs_contain("syslog_level=\"warning\""));
+
+    drop(unsafe { Context::from_raw(SapiGlobals::get().server_context) });
+    SapiGlobals::get_mut().server_context = std::ptr::null_mut();
   }
 
   #[test]
@@ -187,4 +271,75 @@ mod tests {
     let context = Context::from_server_context(SapiGlobals::get().server_context);
     assert!(context.is_request_finished());
   }
+
+  #[test]
+  fn test_getenv() {
+    let _guard = SapiTestGuard::new();
+
+    assert!(unsafe { Sapi::getenv(c"APP_ENV".as_ptr(), 7) }.is_null());
+
+    let variables = RequestVariables {
+      server: Default::default(),
+      env: HashMap::from([("APP_ENV".to_string(), "prod".to_string())]),
+    };
+    let context = ContextBuilder::default().variables(variables).build();
+    SapiGlobals::get_mut().server_context = context.into_raw().cast();
+
+    let value = unsafe { Sapi::getenv(c"APP_ENV".as_ptr(), 7) };
+    assert_eq!(unsafe { CStr::from_ptr(value) }, c"prod");
+    assert!(unsafe { Sapi::getenv(c"APP_DEBUG".as_ptr(), 9) }.is_null());
+
+    drop(unsafe { Context::from_raw(SapiGlobals::get().server_context) });
+    SapiGlobals::get_mut().server_context = std::ptr::null_mut();
+  }
+
+  #[test]
+  fn test_activate() {
+    let _guard = SapiTestGuard::new();
+
+    let ini = IniEntries {
+      values: HashMap::from([("max_execution_time".to_string(), "123".to_string())]),
+      admin_values: Default::default(),
+    };
+    let context = ContextBuilder::default().ini(ini).build();
+    SapiGlobals::get_mut().server_context = context.into_raw().cast();
+
+    assert_eq!(unsafe { pasir_sys::php_request_startup() }, ZEND_RESULT_CODE_SUCCESS);
+    assert_eq!(ExecutorGlobals::get().timeout_seconds, 123);
+    unsafe { pasir_sys::php_request_shutdown(std::ptr::null_mut()) };
+    assert_ne!(ExecutorGlobals::get().timeout_seconds, 123);
+  }
+
+  #[test]
+  fn test_activate_admin_values() {
+    let _guard = SapiTestGuard::new();
+
+    let ini = IniEntries {
+      values: HashMap::from([("memory_limit".to_string(), "256M".to_string())]),
+      admin_values: HashMap::from([("max_execution_time".to_string(), "123".to_string())]),
+    };
+    let context = ContextBuilder::default().ini(ini).build();
+    SapiGlobals::get_mut().server_context = context.into_raw().cast();
+
+    // Alters the entries the way `ini_set()` does.
+    let ini_set = |name: &str, value: &str| {
+      let mut name = ZendStr::new(name, false);
+      unsafe {
+        pasir_sys::zend_alter_ini_entry_chars(
+          &mut *name,
+          value.as_ptr().cast(),
+          value.len(),
+          ZEND_INI_USER.cast_signed(),
+          ZEND_INI_STAGE_RUNTIME.cast_signed(),
+        )
+      }
+    };
+
+    assert_eq!(unsafe { pasir_sys::php_request_startup() }, ZEND_RESULT_CODE_SUCCESS);
+    assert_eq!(ini_set("memory_limit", "512M"), ZEND_RESULT_CODE_SUCCESS);
+    assert_eq!(ini_set("max_execution_time", "5"), ZEND_RESULT_CODE_FAILURE);
+    assert_eq!(ExecutorGlobals::get().timeout_seconds, 123);
+    unsafe { pasir_sys::php_request_shutdown(std::ptr::null_mut()) };
+    assert_ne!(ExecutorGlobals::get().timeout_seconds, 123);
+  }
 }
@@ -0,0 +1,250 @@
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use headers::Authorization;
+use headers::HeaderMapExt;
+use headers::authorization::Basic;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::body::Body;
+use hyper::body::Frame;
+use hyper::body::SizeHint;
+use hyper::header::REFERER;
+use hyper::header::USER_AGENT;
+use tower::Layer;
+use tower::Service;
+
+use crate::access_log::AccessLog;
+use crate::access_log::Entry;
+use crate::config::route::ServedRoute;
+use crate::config::route::remote_addr;
+use crate::service::php::PhpExecution;
+
+/// Writes a line to the access log for every request, once its response body has been sent.
+#[derive(Clone, Default)]
+pub(crate) struct AccessLogLayer {
+  access_log: Option<AccessLog>,
+}
+
+impl AccessLogLayer {
+  pub(crate) fn new(access_log: Option<AccessLog>) -> Self {
+    Self { access_log }
+  }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+  type Service = AccessLogService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    AccessLogService {
+      inner,
+      access_log: self.access_log.clone(),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct AccessLogService<S> {
+  inner: S,
+  access_log: Option<AccessLog>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessLogService<S>
+where
+  S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+  S::Future: Send + 'static,
+  S::Error: Send + 'static,
+  ResBody: Body<Data = Bytes> + Send + 'static,
+{
+  type Response = Response<AccessLogBody<ResBody>>;
+  type Error = S::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    let Some(access_log) = self.access_log.clone() else {
+      let future = self.inner.call(req);
+      return Box::pin(async move { Ok(future.await?.map(|body| AccessLogBody::new(body, None))) });
+    };
+
+    let start = Instant::now();
+    let headers = req.headers();
+    let entry = Entry {
+      time: SystemTime::now(),
+      remote_addr: remote_addr(&req),
+      user: headers
+        .typed_get::<Authorization<Basic>>()
+        .map(|credentials| credentials.username().to_string()),
+      method: req.method().clone(),
+      uri: req.uri().clone(),
+      version: req.version(),
+      status: StatusCode::OK,
+      size: 0,
+      referer: headers.get(REFERER).cloned(),
+      user_agent: headers.get(USER_AGENT).cloned(),
+      request_id: headers.get("x-request-id").cloned(),
+      script: None,
+      php_time: None,
+      duration: Default::default(),
+      route: None,
+    };
+    let future = self.inner.call(req);
+
+    Box::pin(async move {
+      let response = future.await?;
+      let mut entry = Entry {
+        status: response.status(),
+        route: response.extensions().get::<ServedRoute>().and_then(|route| route.0),
+        ..entry
+      };
+      if entry.status == StatusCode::UNAUTHORIZED {
+        entry.user = None;
+      }
+      let execution = response.extensions().get::<PhpExecution>().cloned();
+      let pending = PendingEntry {
+        access_log,
+        entry,
+        start,
+        execution,
+      };
+      Ok(response.map(|body| AccessLogBody::new(body, Some(pending))))
+    })
+  }
+}
+
+struct PendingEntry {
+  access_log: AccessLog,
+  entry: Entry,
+  start: Instant,
+  execution: Option<PhpExecution>,
+}
+
+/// Response body counting the bytes sent, logging the request when it is dropped.
+pub(crate) struct AccessLogBody<B> {
+  inner: Pin<Box<B>>,
+  pending: Option<PendingEntry>,
+}
+
+impl<B> AccessLogBody<B> {
+  fn new(inner: B, pending: Option<PendingEntry>) -> Self {
+    Self {
+      inner: Box::pin(inner),
+      pending,
+    }
+  }
+}
+
+impl<B> Body for AccessLogBody<B>
+where
+  B: Body<Data = Bytes>,
+{
+  type Data = Bytes;
+  type Error = B::Error;
+
+  fn poll_frame(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let poll = self.inner.as_mut().poll_frame(cx);
+    if let (Poll::Ready(Some(Ok(frame))), Some(pending)) = (&poll, self.pending.as_mut())
+      && let Some(data) = frame.data_ref()
+    {
+      pending.entry.size += data.len() as u64;
+    }
+    poll
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+impl<B> Drop for AccessLogBody<B> {
+  fn drop(&mut self) {
+    if let Some(PendingEntry {
+      access_log,
+      mut entry,
+      start,
+      execution,
+    }) = self.pending.take()
+    {
+      entry.duration = start.elapsed();
+      if let Some(execution) = execution {
+        entry.script = execution.script().map(str::to_string);
+        entry.php_time = execution.duration();
+      }
+      access_log.log(&entry);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::Infallible;
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Empty;
+  use http_body_util::Full;
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use tower::Layer;
+  use tower::ServiceExt;
+  use tower::service_fn;
+
+  use crate::access_log::AccessLog;
+  use crate::access_log::AccessLogFormat;
+  use crate::cli::serve::Stream;
+  use crate::config::route::ServedRoute;
+  use crate::service::access_log::AccessLogLayer;
+
+  #[tokio::test]
+  async fn test_access_log() {
+    let (access_log, mut rx) = AccessLog::channel(AccessLogFormat::Json);
+    let service = AccessLogLayer::new(Some(access_log)).layer(service_fn(|_| async {
+      let response = Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .extension(ServedRoute(Some(1)))
+        .body(Full::from("not found"))
+        .unwrap();
+      Ok::<_, Infallible>(response)
+    }));
+
+    let request = Request::builder()
+      .uri("/foo?bar=1")
+      .header("Authorization", "Basic YWxpY2U6c2VjcmV0")
+      .header("User-Agent", "curl/8.0")
+      .header("X-Request-Id", "f81d4fae")
+      .extension(Arc::new(Stream::default()))
+      .body(Empty::<Bytes>::default())
+      .unwrap();
+    let response = service.oneshot(request).await.unwrap();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "not found");
+
+    let line = rx.try_recv().unwrap();
+    for field in [
+      "\"remote_addr\":\"127.0.0.1\"",
+      "\"user\":\"alice\"",
+      "\"uri\":\"/foo?bar=1\"",
+      "\"status\":404",
+      "\"size\":9",
+      "\"user_agent\":\"curl/8.0\"",
+      "\"request_id\":\"f81d4fae\"",
+      "\"script\":null",
+      "\"route\":1",
+    ] {
+      assert!(line.contains(field), "{field} missing from {line}");
+    }
+  }
+}
@@ -0,0 +1,499 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Instant;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use hyper::HeaderMap;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::AGE;
+use hyper::http::HeaderName;
+use hyper::http::HeaderValue;
+use tower::Layer;
+use tower::Service;
+use tracing::debug;
+
+use crate::config::cache::Cache;
+use crate::config::cache::CacheKey;
+use crate::config::route::remote_addr;
+use crate::config::route::request_host;
+use crate::sapi::context::ResponseType;
+use crate::sapi::context::SERVER_TIMING;
+use crate::service::ResponseBody;
+
+static X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// Shared store of the microcache, bounded by the `max_size` memory budget with LRU eviction.
+pub(crate) struct Microcache {
+  config: Cache,
+  store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+  entries: HashMap<CacheKey, Entry>,
+  /// Keys by their last use, the least recently used first.
+  lru: BTreeMap<u64, CacheKey>,
+  tick: u64,
+  size: u64,
+}
+
+struct Entry {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Bytes,
+  stored_at: Instant,
+  fresh_until: Instant,
+  stale_until: Instant,
+  size: u64,
+  tick: u64,
+  refreshing: bool,
+}
+
+enum Lookup {
+  Hit(Response<ResponseBody>),
+  /// A stale response, and whether the caller must refresh the entry.
+  Stale(Response<ResponseBody>, bool),
+  Miss,
+}
+
+impl Entry {
+  fn response(&self, now: Instant, status: &'static str) -> Response<ResponseBody> {
+    let mut response = Response::new(Full::new(self.body.clone()).boxed_unsync());
+    *response.status_mut() = self.status;
+    *response.headers_mut() = self.headers.clone();
+    response
+      .headers_mut()
+      .insert(AGE, HeaderValue::from((now - self.stored_at).as_secs()));
+    response.headers_mut().insert(X_CACHE.clone(), HeaderValue::from_static(status));
+    response.extensions_mut().insert(ResponseType::Full);
+    response
+  }
+}
+
+impl Store {
+  fn lookup(&mut self, key: &CacheKey, now: Instant) -> Lookup {
+    let Some(entry) = self.entries.get_mut(key) else {
+      return Lookup::Miss;
+    };
+    if now >= entry.stale_until {
+      self.remove(key);
+      return Lookup::Miss;
+    }
+
+    self.lru.remove(&entry.tick);
+    self.tick += 1;
+    entry.tick = self.tick;
+    self.lru.insert(self.tick, key.clone());
+    if now < entry.fresh_until {
+      return Lookup::Hit(entry.response(now, "HIT"));
+    }
+    let refresh = !entry.refreshing;
+    entry.refreshing = true;
+    Lookup::Stale(entry.response(now, "STALE"), refresh)
+  }
+
+  fn insert(&mut self, key: CacheKey, mut entry: Entry, max_size: u64) {
+    self.remove(&key);
+    if entry.size > max_size {
+      return;
+    }
+    while self.size + entry.size > max_size
+      && let Some((_, evicted)) = self.lru.pop_first()
+    {
+      debug!("Evicting {evicted:?} from the cache");
+      if let Some(evicted) = self.entries.remove(&evicted) {
+        self.size -= evicted.size;
+      }
+    }
+
+    self.tick += 1;
+    entry.tick = self.tick;
+    self.size += entry.size;
+    self.lru.insert(self.tick, key.clone());
+    self.entries.insert(key, entry);
+  }
+
+  fn remove(&mut self, key: &CacheKey) {
+    if let Some(entry) = self.entries.remove(key) {
+      self.lru.remove(&entry.tick);
+      self.size -= entry.size;
+    }
+  }
+}
+
+impl Microcache {
+  pub(crate) fn new(config: Cache) -> Self {
+    Self {
+      config,
+      store: Mutex::new(Store::default()),
+    }
+  }
+
+  fn lookup(&self, key: &CacheKey) -> Lookup {
+    self.lookup_at(key, Instant::now())
+  }
+
+  fn lookup_at(&self, key: &CacheKey, now: Instant) -> Lookup {
+    self.store.lock().unwrap().lookup(key, now)
+  }
+
+  /// Stores a cacheable PHP response, or drops the entry it was refreshing otherwise.
+  async fn store(&self, key: CacheKey, response: Response<ResponseBody>) -> Response<ResponseBody> {
+    self.store_at(key, response, Instant::now()).await
+  }
+
+  async fn store_at(&self, key: CacheKey, response: Response<ResponseBody>, now: Instant) -> Response<ResponseBody> {
+    let full = matches!(response.extensions().get::<ResponseType>(), Some(ResponseType::Full));
+    let Some(lifetimes) = self.config.lifetimes(&response).filter(|_| full) else {
+      self.store.lock().unwrap().remove(&key);
+      return response;
+    };
+
+    let (mut head, body) = response.into_parts();
+    let body = match body.collect().await {
+      Ok(collected) => collected.to_bytes(),
+      Err(e) => match e {},
+    };
+    let headers_size = head
+      .headers
+      .iter()
+      .map(|(name, value)| name.as_str().len() + value.len())
+      .sum::<usize>();
+    // Timings are measured for the response storing the entry, not the hits.
+    let mut headers = head.headers.clone();
+    headers.remove(&SERVER_TIMING);
+    let entry = Entry {
+      status: head.status,
+      headers,
+      size: (key.size() + headers_size + body.len()) as u64,
+      body: body.clone(),
+      stored_at: now,
+      fresh_until: now + lifetimes.fresh,
+      stale_until: now + lifetimes.fresh + lifetimes.stale,
+      tick: 0,
+      refreshing: false,
+    };
+    self.store.lock().unwrap().insert(key, entry, self.config.max_size());
+
+    head.headers.insert(X_CACHE.clone(), HeaderValue::from_static("MISS"));
+    Response::from_parts(head, Full::new(body).boxed_unsync())
+  }
+
+  /// Removes the entries of `path` on the request host, or of every path under it when it ends with `*`.
+  fn purge(&self, host: &str, path: &str) -> usize {
+    let mut store = self.store.lock().unwrap();
+    let keys = store
+      .entries
+      .keys()
+      .filter(|key| key.matches_purge(host, path))
+      .cloned()
+      .collect::<Vec<_>>();
+    keys.iter().for_each(|key| store.remove(key));
+    keys.len()
+  }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CacheLayer {
+  cache: Option<Arc<Microcache>>,
+}
+
+impl CacheLayer {
+  pub(crate) fn new(config: Option<Cache>) -> Self {
+    Self {
+      cache: config.map(|config| Arc::new(Microcache::new(config))),
+    }
+  }
+}
+
+impl<S> Layer<S> for CacheLayer {
+  type Service = CacheService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    CacheService {
+      inner,
+      cache: self.cache.clone(),
+    }
+  }
+}
+
+/// Serves PHP responses from the microcache, refreshing stale entries in the background with a
+/// single request while the stale response is served.
+#[derive(Clone)]
+pub(crate) struct CacheService<S> {
+  inner: S,
+  cache: Option<Arc<Microcache>>,
+}
+
+impl<S> CacheService<S> {
+  /// Answers a `PURGE` request from an allowed client address, if the cache is enabled.
+  pub(crate) fn purge<B>(&self, request: &Request<B>) -> Option<Response<ResponseBody>> {
+    let cache = self.cache.as_ref().filter(|_| request.method().as_str() == "PURGE")?;
+    if !remote_addr(request).is_some_and(|addr| cache.config.allows_purge(addr)) {
+      let mut response = Response::<ResponseBody>::default();
+      *response.status_mut() = StatusCode::FORBIDDEN;
+      return Some(response);
+    }
+
+    let host = request_host(request).unwrap_or_default();
+    let count = cache.purge(&host, request.uri().path());
+    debug!("Purged {count} cache entries of {}{}", host, request.uri().path());
+    Some(Response::new(Full::from(format!("Purged {count} entries\n")).boxed_unsync()))
+  }
+}
+
+impl<S, B> Service<Request<B>> for CacheService<S>
+where
+  S: Service<Request<B>, Response = Response<ResponseBody>>,
+  S::Future: Send + 'static,
+  S::Error: Send + 'static,
+{
+  type Response = Response<ResponseBody>;
+  type Error = S::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<B>) -> Self::Future {
+    let Some((cache, key)) = self.cache.clone().and_then(|cache| cache.config.key(&req).map(|key| (cache, key))) else {
+      return Box::pin(self.inner.call(req));
+    };
+
+    match cache.lookup(&key) {
+      Lookup::Hit(response) => Box::pin(async move { Ok(response) }),
+      Lookup::Stale(response, refresh) => {
+        if refresh {
+          let future = self.inner.call(req);
+          tokio::spawn(async move {
+            match future.await {
+              Ok(response) => drop(cache.store(key, response).await),
+              Err(_) => cache.store.lock().unwrap().remove(&key),
+            }
+          });
+        }
+        Box::pin(async move { Ok(response) })
+      }
+      Lookup::Miss => {
+        let future = self.inner.call(req);
+        Box::pin(async move {
+          let response = future.await?;
+          Ok(cache.store(key, response).await)
+        })
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::Infallible;
+  use std::sync::Arc;
+  use std::sync::atomic::AtomicUsize;
+  use std::sync::atomic::Ordering;
+  use std::time::Duration;
+  use std::time::Instant;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Empty;
+  use http_body_util::Full;
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use tower::Layer;
+  use tower::Service;
+  use tower::ServiceExt;
+  use tower::service_fn;
+
+  use crate::cli::serve::Stream;
+  use crate::sapi::context::ResponseType;
+  use crate::service::ResponseBody;
+  use crate::service::cache::CacheLayer;
+  use crate::service::cache::Lookup;
+
+  fn layer(config: &str) -> CacheLayer {
+    CacheLayer::new(Some(toml::from_str(config).unwrap()))
+  }
+
+  fn counting_service(
+    cache_control: &'static str,
+    calls: Arc<AtomicUsize>,
+  ) -> impl Service<Request<Empty<Bytes>>, Response = Response<ResponseBody>, Error = Infallible, Future: Send + 'static> + Clone {
+    service_fn(move |_: Request<Empty<Bytes>>| {
+      let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+      async move {
+        Ok::<_, Infallible>(
+          Response::builder()
+            .header("Cache-Control", cache_control)
+            .extension(ResponseType::Full)
+            .body(Full::from(format!("response {count}")).boxed_unsync())
+            .unwrap(),
+        )
+      }
+    })
+  }
+
+  fn request(uri: &str) -> Request<Empty<Bytes>> {
+    Request::builder().uri(uri).body(Empty::default()).unwrap()
+  }
+
+  async fn body(response: Response<ResponseBody>) -> Bytes {
+    response.into_body().collect().await.unwrap().to_bytes()
+  }
+
+  #[tokio::test]
+  async fn test_cache_hit() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut service = layer("").layer(counting_service("public, s-maxage=60", calls.clone()));
+
+    let response = service
+      .ready()
+      .await
+      .unwrap()
+      .call(request("http://example.com/foo"))
+      .await
+      .unwrap();
+    assert_eq!(response.headers()["X-Cache"], "MISS");
+    assert_eq!(body(response).await, "response 1");
+
+    let response = service
+      .ready()
+      .await
+      .unwrap()
+      .call(request("http://example.com/foo"))
+      .await
+      .unwrap();
+    assert_eq!(response.headers()["X-Cache"], "HIT");
+    assert_eq!(response.headers()["Age"], "0");
+    assert_eq!(body(response).await, "response 1");
+
+    let response = service
+      .ready()
+      .await
+      .unwrap()
+      .call(request("http://example.com/foo?bar"))
+      .await
+      .unwrap();
+    assert_eq!(body(response).await, "response 2");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn test_cache_uncacheable() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut service = layer("").layer(counting_service("public, max-age=60", calls.clone()));
+
+    for _ in 0..2 {
+      let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("http://example.com/foo"))
+        .await
+        .unwrap();
+      assert!(!response.headers().contains_key("X-Cache"));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn test_stale_while_revalidate() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut inner = counting_service("public, s-maxage=1, stale-while-revalidate=60", calls.clone());
+    let cache = layer("").cache.unwrap();
+    let key = cache.config.key(&request("http://example.com/foo")).unwrap();
+    let now = Instant::now();
+    let response = inner.ready().await.unwrap().call(request("http://example.com/foo")).await.unwrap();
+    cache.store_at(key.clone(), response, now).await;
+
+    // Only the first stale lookup refreshes the entry.
+    let later = now + Duration::from_millis(1100);
+    for expected in [true, false, false] {
+      let Lookup::Stale(response, refresh) = cache.lookup_at(&key, later) else {
+        panic!("Expected a stale entry");
+      };
+      assert_eq!(refresh, expected);
+      assert_eq!(response.headers()["X-Cache"], "STALE");
+      assert_eq!(body(response).await, "response 1");
+    }
+
+    let response = inner.ready().await.unwrap().call(request("http://example.com/foo")).await.unwrap();
+    cache.store_at(key.clone(), response, later).await;
+    let Lookup::Hit(response) = cache.lookup_at(&key, later) else {
+      panic!("Expected a fresh entry");
+    };
+    assert_eq!(body(response).await, "response 2");
+    assert!(matches!(cache.lookup_at(&key, later + Duration::from_secs(61)), Lookup::Miss));
+  }
+
+  #[tokio::test]
+  async fn test_lru_eviction() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut service = layer("max_size = 150").layer(counting_service("public, s-maxage=60", calls.clone()));
+
+    for uri in [
+      "http://example.com/a",
+      "http://example.com/b",
+      "http://example.com/a",
+      "http://example.com/c",
+    ] {
+      service.ready().await.unwrap().call(request(uri)).await.unwrap();
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let response = service.ready().await.unwrap().call(request("http://example.com/a")).await.unwrap();
+    assert_eq!(response.headers()["X-Cache"], "HIT");
+    let response = service.ready().await.unwrap().call(request("http://example.com/b")).await.unwrap();
+    assert_eq!(response.headers()["X-Cache"], "MISS");
+  }
+
+  #[tokio::test]
+  async fn test_purge() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut service = layer(r#"purge_from = ["127.0.0.1"]"#).layer(counting_service("public, s-maxage=60", calls.clone()));
+    for uri in ["http://example.com/blog/1", "http://example.com/blog/2", "http://example.com/about"] {
+      service.ready().await.unwrap().call(request(uri)).await.unwrap();
+    }
+
+    let purge = |peer: &str| {
+      Request::builder()
+        .method("PURGE")
+        .uri("http://example.com/blog/*")
+        .extension(Arc::new(Stream::new("127.0.0.1:80".parse().unwrap(), peer.parse().unwrap())))
+        .body(Empty::<Bytes>::default())
+        .unwrap()
+    };
+    assert!(service.purge(&request("http://example.com/blog/1")).is_none());
+    assert_eq!(service.purge(&purge("192.168.1.1:1234")).unwrap().status(), StatusCode::FORBIDDEN);
+
+    let response = service.purge(&purge("127.0.0.1:1234")).unwrap();
+    assert_eq!(body(response).await, "Purged 2 entries\n");
+
+    let response = service
+      .ready()
+      .await
+      .unwrap()
+      .call(request("http://example.com/about"))
+      .await
+      .unwrap();
+    assert_eq!(response.headers()["X-Cache"], "HIT");
+    let response = service
+      .ready()
+      .await
+      .unwrap()
+      .call(request("http://example.com/blog/1"))
+      .await
+      .unwrap();
+    assert_eq!(response.headers()["X-Cache"], "MISS");
+  }
+}
@@ -0,0 +1,349 @@
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use http_body_util::Full;
+use hyper::HeaderMap;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::Uri;
+use hyper::Version;
+use hyper::body::Body;
+use hyper::header::ACCEPT;
+use hyper::header::ACCEPT_LANGUAGE;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::CONTENT_TYPE;
+use hyper::header::COOKIE;
+use hyper::header::HOST;
+use hyper::header::REFERER;
+use hyper::header::USER_AGENT;
+use hyper::http::HeaderName;
+use hyper::http::HeaderValue;
+use pasir_sapi::error::LastError;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::debug;
+
+use crate::cli::serve::Stream;
+use crate::config::error::ErrorPage;
+use crate::config::error::ErrorPages;
+use crate::config::route::Routes;
+use crate::config::route::ServedRoute;
+use crate::config::route::join_root;
+use crate::sapi::context::RequestVariables;
+use crate::sapi::context::ScriptName;
+use crate::service::PhpService;
+use crate::service::ResponseBody;
+use crate::service::listing::escape_html;
+
+/// Development error page detailing the PHP error that failed a request.
+pub(crate) fn debug_page(error: &LastError) -> Response<ResponseBody> {
+  let html = format!(
+    "<!DOCTYPE html>\n<html>\n<head><title>PHP {kind}</title></head>\n<body>\n<h1>PHP {kind}</h1>\n\
+     <pre>{message}</pre>\n<p>in <code>{file}</code> on line {line}</p>\n</body>\n</html>\n",
+    kind = error.kind(),
+    message = escape_html(&error.message),
+    file = escape_html(&error.file),
+    line = error.line,
+  );
+  let mut response = Response::new(Full::from(html).boxed_unsync());
+  *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+  let headers = response.headers_mut();
+  headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+  headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+  response
+}
+
+/// Request headers passed on to error pages, e.g. for a PHP page to answer in the language of the client.
+static ERROR_REQUEST_HEADERS: [HeaderName; 7] = [
+  HOST,
+  ACCEPT,
+  ACCEPT_LANGUAGE,
+  COOKIE,
+  REFERER,
+  USER_AGENT,
+  HeaderName::from_static("x-request-id"),
+];
+
+/// Part of the original request head kept to request an error page, which is only built when the
+/// response needs one.
+struct ErrorRequest {
+  head: bool,
+  uri: Uri,
+  version: Version,
+  headers: HeaderMap,
+  stream: Option<Arc<Stream>>,
+}
+
+impl ErrorRequest {
+  fn new<B>(request: &Request<B>) -> Self {
+    let mut headers = HeaderMap::new();
+    for name in &ERROR_REQUEST_HEADERS {
+      for value in request.headers().get_all(name) {
+        headers.append(name.clone(), value.clone());
+      }
+    }
+    Self {
+      head: request.method() == Method::HEAD,
+      uri: request.uri().clone(),
+      version: request.version(),
+      headers,
+      stream: request.extensions().get::<Arc<Stream>>().cloned(),
+    }
+  }
+
+  /// Builds the error page request, without the body of the original request nor its conditional or
+  /// range headers, so the page is served whole.
+  fn into_request(self, status: StatusCode) -> Request<Empty<Bytes>> {
+    let mut request = Request::new(Empty::<Bytes>::new());
+    if self.head {
+      *request.method_mut() = Method::HEAD;
+    }
+    *request.uri_mut() = self.uri;
+    *request.version_mut() = self.version;
+    *request.headers_mut() = self.headers;
+    if let Some(stream) = self.stream {
+      request.extensions_mut().insert(stream);
+    }
+    let variables = request.extensions_mut().get_or_insert_default::<RequestVariables>();
+    variables.server.insert("REDIRECT_STATUS".to_string(), status.as_str().to_string());
+    request
+  }
+}
+
+/// Replaces error responses without a body by the error page configured for their status code.
+#[derive(Clone)]
+pub(crate) struct ErrorPageLayer {
+  php: PhpService,
+}
+
+impl ErrorPageLayer {
+  pub(crate) fn new(php: PhpService) -> Self {
+    Self { php }
+  }
+}
+
+impl<S> Layer<S> for ErrorPageLayer {
+  type Service = ErrorPageService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    ErrorPageService {
+      inner,
+      php: self.php.clone(),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct ErrorPageService<S> {
+  inner: S,
+  php: PhpService,
+}
+
+impl<S> ErrorPageService<S> {
+  fn is_empty(response: &Response<ResponseBody>) -> bool {
+    let body = response.body();
+    body.is_end_stream() || body.size_hint().exact() == Some(0)
+  }
+
+  async fn render(php: PhpService, root: Arc<PathBuf>, page: &ErrorPage, request: Request<Empty<Bytes>>) -> Option<Response<ResponseBody>> {
+    match page {
+      ErrorPage::Static(path) => {
+        let file = join_root(&root, path)?;
+        let response = ServeFile::new(file).oneshot(request).await.ok()?;
+        if !response.status().is_success() {
+          debug!("Error page {path} could not be served: {}", response.status());
+          return None;
+        }
+        Some(response.map(|body| body.map_err(|_| unreachable!()).boxed_unsync()))
+      }
+      ErrorPage::Php(script_name) => {
+        let mut request = request;
+        request.extensions_mut().insert(root);
+        request.extensions_mut().insert(ScriptName(script_name.clone()));
+        php.oneshot(request).await.ok()
+      }
+    }
+  }
+}
+
+impl<S, B> Service<Request<B>> for ErrorPageService<S>
+where
+  S: Service<Request<B>, Response = Response<ResponseBody>>,
+  S::Future: Send + 'static,
+  S::Error: Send + 'static,
+{
+  type Response = Response<ResponseBody>;
+  type Error = S::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<B>) -> Self::Future {
+    let Some(routes) = req.extensions().get::<Arc<Routes>>().cloned() else {
+      return Box::pin(self.inner.call(req));
+    };
+    let (root, errors) = match routes.site(&req) {
+      Some(site) if !site.routes().errors().is_empty() => (Arc::new(site.root().to_path_buf()), site.routes().errors().clone()),
+      Some(site) => (Arc::new(site.root().to_path_buf()), routes.errors().clone()),
+      None => (req.extensions().get::<Arc<PathBuf>>().unwrap().clone(), routes.errors().clone()),
+    };
+    if errors.is_empty() {
+      return Box::pin(self.inner.call(req));
+    }
+
+    let error_request = ErrorRequest::new(&req);
+    let php = self.php.clone();
+    let future = self.inner.call(req);
+
+    Box::pin(async move {
+      let response = future.await?;
+      let status = response.status();
+      let Some(page) = errors.get(status).filter(|_| Self::is_empty(&response)) else {
+        return Ok(response);
+      };
+
+      match Self::render(php, root, page, error_request.into_request(status)).await {
+        None => Ok(response),
+        Some(mut error_response) => {
+          if error_response.status() == StatusCode::OK {
+            *error_response.status_mut() = status;
+          }
+          if let Some(served_route) = response.extensions().get::<ServedRoute>() {
+            error_response.extensions_mut().insert(*served_route);
+          }
+          Ok(error_response)
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::Infallible;
+  use std::path::PathBuf;
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Empty;
+  use http_body_util::Full;
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use pasir_sapi::error::LastError;
+  use tower::Layer;
+  use tower::ServiceExt;
+  use tower::service_fn;
+
+  use crate::config::route::Routes;
+  use crate::service::PhpService;
+  use crate::service::error::ErrorPageLayer;
+  use crate::service::error::debug_page;
+
+  fn request() -> Request<Empty<Bytes>> {
+    let routes: Routes = toml::from_str(
+      r#"
+      [errors]
+      403 = "/errors/403.html"
+      404 = "/errors/404.html"
+      "#,
+    )
+    .unwrap();
+
+    Request::builder()
+      .uri("/bar")
+      .extension(Arc::new(PathBuf::from("tests/fixtures/root")))
+      .extension(Arc::new(routes))
+      .body(Empty::default())
+      .unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_error_page_keeps_non_empty_body() {
+    let service = ErrorPageLayer::new(PhpService::default()).layer(service_fn(|_| async {
+      let mut response = Response::new(Full::from("Not here").boxed_unsync());
+      *response.status_mut() = StatusCode::NOT_FOUND;
+      Ok::<_, Infallible>(response)
+    }));
+
+    let response = service.oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "Not here");
+  }
+
+  #[tokio::test]
+  async fn test_error_page_missing_file() {
+    let service = ErrorPageLayer::new(PhpService::default()).layer(service_fn(|_| async {
+      let mut response = Response::new(Empty::default().boxed_unsync());
+      *response.status_mut() = StatusCode::NOT_FOUND;
+      Ok::<_, Infallible>(response)
+    }));
+
+    let response = service.oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.into_body().collect().await.unwrap().to_bytes().is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_error_page_static() {
+    let service = ErrorPageLayer::new(PhpService::default()).layer(service_fn(|_| async {
+      let mut response = Response::new(Empty::default().boxed_unsync());
+      *response.status_mut() = StatusCode::FORBIDDEN;
+      Ok::<_, Infallible>(response)
+    }));
+
+    let response = service.oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "Forbidden\n");
+  }
+
+  #[tokio::test]
+  async fn test_error_page_ignores_conditional_headers() {
+    let service = ErrorPageLayer::new(PhpService::default()).layer(service_fn(|_| async {
+      let mut response = Response::new(Empty::default().boxed_unsync());
+      *response.status_mut() = StatusCode::FORBIDDEN;
+      Ok::<_, Infallible>(response)
+    }));
+
+    let mut request = request();
+    request.headers_mut().insert("Range", "bytes=0-2".parse().unwrap());
+    request.headers_mut().insert("If-None-Match", "*".parse().unwrap());
+    request
+      .headers_mut()
+      .insert("If-Modified-Since", "Sun, 18 Oct 2099 00:00:00 GMT".parse().unwrap());
+    let response = service.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "Forbidden\n");
+  }
+
+  #[tokio::test]
+  async fn test_debug_page() {
+    let error = LastError {
+      error_type: 1,
+      message: "Uncaught Exception: <foo>".to_string(),
+      file: "/srv/www/index.php".to_string(),
+      line: 3,
+    };
+
+    let response = debug_page(&error);
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("<h1>PHP Fatal error</h1>"));
+    assert!(body.contains("<pre>Uncaught Exception: &lt;foo&gt;</pre>"));
+    assert!(body.contains("<code>/srv/www/index.php</code> on line 3"));
+  }
+}
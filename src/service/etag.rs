@@ -0,0 +1,220 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use http_body_util::Full;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::CONTENT_LENGTH;
+use hyper::header::ETAG;
+use hyper::header::IF_NONE_MATCH;
+use hyper::http::HeaderValue;
+use md5::Digest;
+use md5::Md5;
+use tower::Layer;
+use tower::Service;
+
+use crate::config::route::Routes;
+use crate::sapi::context::ResponseType;
+use crate::service::ResponseBody;
+
+/// Tags full PHP responses with a weak `ETag` hashed from their body, and answers a matching
+/// `If-None-Match` with `304 Not Modified`.
+///
+/// Enabled by `etag = true`. Streamed responses and responses with `Cache-Control: no-store` are left
+/// untouched, and an `ETag` set by PHP is kept.
+#[derive(Clone, Default)]
+pub(crate) struct EtagLayer;
+
+impl<S> Layer<S> for EtagLayer {
+  type Service = EtagService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    EtagService { inner }
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct EtagService<S> {
+  inner: S,
+}
+
+impl<S> EtagService<S> {
+  fn is_cacheable(response: &Response<ResponseBody>) -> bool {
+    response.status() == StatusCode::OK
+      && matches!(response.extensions().get::<ResponseType>(), Some(ResponseType::Full))
+      && !response
+        .headers()
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+  }
+
+  fn matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+      return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+      .split(',')
+      .map(str::trim)
+      .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+  }
+}
+
+impl<S, B> Service<Request<B>> for EtagService<S>
+where
+  S: Service<Request<B>, Response = Response<ResponseBody>>,
+  S::Future: Send + 'static,
+  S::Error: Send + 'static,
+{
+  type Response = Response<ResponseBody>;
+  type Error = S::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<B>) -> Self::Future {
+    let enabled = req.extensions().get::<Arc<Routes>>().is_some_and(|routes| routes.etag(&req));
+    if !enabled || !matches!(*req.method(), Method::GET | Method::HEAD) {
+      return Box::pin(self.inner.call(req));
+    }
+
+    let if_none_match = req.headers().get(IF_NONE_MATCH).cloned();
+    let future = self.inner.call(req);
+
+    Box::pin(async move {
+      let response = future.await?;
+      if !Self::is_cacheable(&response) {
+        return Ok(response);
+      }
+
+      let (mut head, body) = response.into_parts();
+      let (etag, body) = match head.headers.get(ETAG) {
+        Some(etag) => (etag.clone(), body),
+        None => {
+          let bytes: Bytes = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(e) => match e {},
+          };
+          let etag = HeaderValue::from_str(&format!("W/\"{:x}\"", Md5::digest(&bytes))).unwrap();
+          head.headers.insert(ETAG, etag.clone());
+          (etag, Full::new(bytes).boxed_unsync())
+        }
+      };
+
+      if if_none_match.is_some_and(|if_none_match| Self::matches(&if_none_match, &etag)) {
+        head.status = StatusCode::NOT_MODIFIED;
+        head.headers.remove(CONTENT_LENGTH);
+        return Ok(Response::from_parts(head, Empty::default().boxed_unsync()));
+      }
+      Ok(Response::from_parts(head, body))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::Infallible;
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Empty;
+  use http_body_util::Full;
+  use hyper::Request;
+  use hyper::Response;
+  use hyper::StatusCode;
+  use rstest::rstest;
+  use tower::Layer;
+  use tower::ServiceExt;
+  use tower::service_fn;
+
+  use crate::config::route::Routes;
+  use crate::sapi::context::ResponseType;
+  use crate::service::ResponseBody;
+  use crate::service::etag::EtagLayer;
+
+  const ETAG: &str = "W/\"acbd18db4cc2f85cedef654fccc4a4d8\"";
+
+  async fn call(config: &str, if_none_match: Option<&str>, response: Response<ResponseBody>) -> Response<ResponseBody> {
+    let routes: Routes = toml::from_str(config).unwrap();
+    let mut response = Some(response);
+    let service = EtagLayer.layer(service_fn(move |_| {
+      let response = response.take().unwrap();
+      async move { Ok::<_, Infallible>(response) }
+    }));
+
+    let mut request = Request::builder().extension(Arc::new(routes));
+    if let Some(if_none_match) = if_none_match {
+      request = request.header("If-None-Match", if_none_match);
+    }
+    service.oneshot(request.body(Empty::<Bytes>::default()).unwrap()).await.unwrap()
+  }
+
+  fn php_response(response_type: ResponseType) -> Response<ResponseBody> {
+    Response::builder()
+      .header("Content-Length", "3")
+      .extension(response_type)
+      .body(Full::from("foo").boxed_unsync())
+      .unwrap()
+  }
+
+  fn no_store_response() -> Response<ResponseBody> {
+    let mut response = php_response(ResponseType::Full);
+    response.headers_mut().insert("Cache-Control", "private, no-store".parse().unwrap());
+    response
+  }
+
+  #[rstest]
+  #[case(None, StatusCode::OK)]
+  #[case(Some("\"other\""), StatusCode::OK)]
+  #[case(Some(ETAG), StatusCode::NOT_MODIFIED)]
+  #[case(Some("\"acbd18db4cc2f85cedef654fccc4a4d8\""), StatusCode::NOT_MODIFIED)]
+  #[case(Some("\"other\", W/\"acbd18db4cc2f85cedef654fccc4a4d8\""), StatusCode::NOT_MODIFIED)]
+  #[case(Some("*"), StatusCode::NOT_MODIFIED)]
+  #[tokio::test]
+  async fn test_etag(#[case] if_none_match: Option<&str>, #[case] status: StatusCode) {
+    let response = call("etag = true", if_none_match, php_response(ResponseType::Full)).await;
+    assert_eq!(response.status(), status);
+    assert_eq!(response.headers()["ETag"], ETAG);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    match status {
+      StatusCode::OK => assert_eq!(body, "foo"),
+      _ => assert!(body.is_empty()),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_etag_from_php() {
+    let mut response = php_response(ResponseType::Full);
+    response.headers_mut().insert("ETag", "\"v1\"".parse().unwrap());
+    let response = call("etag = true", Some("\"v1\""), response).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert!(!response.headers().contains_key("Content-Length"));
+  }
+
+  #[rstest]
+  #[case("etag = false", php_response(ResponseType::Full))]
+  #[case("", php_response(ResponseType::Full))]
+  #[case("etag = true", php_response(ResponseType::Chunked))]
+  #[case("etag = true", Response::new(Full::from("foo").boxed_unsync()))]
+  #[case("etag = true", no_store_response())]
+  #[tokio::test]
+  async fn test_no_etag(#[case] config: &str, #[case] response: Response<ResponseBody>) {
+    let response = call(config, Some("*"), response).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("ETag"));
+  }
+}
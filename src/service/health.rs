@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use ext_php_rs::embed::PhpThreadGuard;
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use http_body_util::Full;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use pasir_sapi::context::ServerContext;
+use tokio::sync::Mutex;
+use tower::Service;
+
+use crate::cli::serve::Stream;
+use crate::config::health::Health;
+use crate::config::health::Probe;
+use crate::sapi::context::Context;
+use crate::sapi::context::ContextSender;
+use crate::service::PhpService;
+use crate::service::ResponseBody;
+use crate::service::router::ResponseFuture;
+
+/// Set once graceful shutdown starts, failing the readiness probe while connections drain.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Held by the readiness check in flight, which keeps running on its PHP thread after the probe times out.
+static CHECK: LazyLock<Arc<Mutex<()>>> = LazyLock::new(Arc::default);
+
+pub(crate) fn drain() {
+  DRAINING.store(true, Ordering::Relaxed);
+}
+
+/// Answers a liveness or readiness probe, bypassing the routes.
+pub(crate) fn respond<B>(probe: Probe, health: Health, req: &Request<B>) -> ResponseFuture {
+  let root = req.extensions().get::<Arc<PathBuf>>().unwrap().clone();
+  let stream = req.extensions().get::<Arc<Stream>>().unwrap().clone();
+
+  Box::pin(async move {
+    let result = match probe {
+      Probe::Liveness => Ok(()),
+      Probe::Readiness => ready(&health, root, stream).await,
+    };
+    let (status, body) = match result {
+      Ok(()) => (StatusCode::OK, "ok\n".to_string()),
+      Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, format!("not ready: {reason}\n")),
+    };
+
+    let mut response = Response::new(Full::from(body).boxed_unsync());
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok::<Response<ResponseBody>, _>(response)
+  })
+}
+
+async fn ready(health: &Health, root: Arc<PathBuf>, stream: Arc<Stream>) -> Result<(), &'static str> {
+  if DRAINING.load(Ordering::Relaxed) {
+    return Err("draining");
+  }
+
+  let script = health.script().map(str::to_string);
+  let check = async {
+    // Probes wait for the check in flight rather than piling up blocked PHP threads.
+    let guard = CHECK.clone().lock_owned().await;
+    let check = tokio::spawn(async move {
+      let _guard = guard;
+      match script {
+        Some(script) => run_script(&script, root, stream).await,
+        None => start_request(root, stream).await,
+      }
+    });
+    check.await.unwrap_or(Err("PHP thread panicked"))
+  };
+  tokio::time::timeout(health.timeout(), check).await.unwrap_or(Err("timed out"))
+}
+
+/// Starts and shuts down a PHP request without running a script.
+async fn start_request(root: Arc<PathBuf>, stream: Arc<Stream>) -> Result<(), &'static str> {
+  let result = tokio::task::spawn_blocking(move || {
+    let _guard = PhpThreadGuard::new();
+    unsafe { pasir_sys::zend_update_current_locale() }
+
+    let (_head_rx, _body_rx, context_tx) = ContextSender::receiver();
+    let context = Context::new(root, stream, Request::new(Bytes::new()), context_tx);
+    context.with_request(|| {})
+  })
+  .await;
+
+  match result {
+    Ok(Ok(_)) => Ok(()),
+    Ok(Err(_)) => Err("PHP request startup failed"),
+    Err(_) => Err("PHP thread panicked"),
+  }
+}
+
+/// Runs the health script, which must answer with a successful status.
+async fn run_script(script: &str, root: Arc<PathBuf>, stream: Arc<Stream>) -> Result<(), &'static str> {
+  let request = Request::get(script)
+    .extension(root)
+    .extension(stream)
+    .body(Empty::<Bytes>::new())
+    .map_err(|_| "invalid health script path")?;
+  let Ok(response) = PhpService::default().call(request).await;
+  match response.status().is_success() {
+    true => Ok(()),
+    false => Err("health script failed"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+  use http_body_util::BodyExt;
+  use http_body_util::Empty;
+  use hyper::Request;
+  use hyper::StatusCode;
+
+  use crate::cli::serve::Stream;
+  use crate::config::health::Health;
+  use crate::config::health::Probe;
+  use crate::service::health::respond;
+
+  #[tokio::test]
+  async fn test_liveness() {
+    let request = Request::builder()
+      .uri("/healthz")
+      .extension(Arc::new(PathBuf::from("tests/fixtures/root")))
+      .extension(Arc::new(Stream::default()))
+      .body(Empty::<Bytes>::default())
+      .unwrap();
+
+    let response = respond(Probe::Liveness, Health::default(), &request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "ok\n");
+  }
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use tracing::error;
+
+use crate::config::static_files::StaticFiles;
+use crate::service::ResponseBody;
+
+/// Responds with an HTML listing of `directory`, requested at `path`.
+pub(crate) fn listing(directory: &Path, path: &str, static_files: &StaticFiles) -> Response<ResponseBody> {
+  let mut response = match render(directory, path, static_files) {
+    Ok(html) => Response::new(Full::from(html).boxed_unsync()),
+    Err(e) => {
+      error!("Failed to list {directory:?}: {e}");
+      let mut response = Response::<ResponseBody>::default();
+      *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+      return response;
+    }
+  };
+  response
+    .headers_mut()
+    .insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+  response
+}
+
+/// Renders the entries of `directory`, directories first and hidden or sensitive entries skipped.
+fn render(directory: &Path, path: &str, static_files: &StaticFiles) -> std::io::Result<String> {
+  let mut entries = std::fs::read_dir(directory)?
+    .filter_map(Result::ok)
+    .filter_map(|entry| {
+      let name = entry.file_name().into_string().ok()?;
+      let is_dir = entry.file_type().ok()?.is_dir();
+      (!name.starts_with('.') && !static_files.is_sensitive(&name)).then_some((!is_dir, name))
+    })
+    .collect::<Vec<_>>();
+  entries.sort();
+
+  let title = escape_html(&format!("Index of {path}"));
+  let mut html = format!("<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<ul>\n");
+  if path != "/" {
+    html.push_str("<li><a href=\"../\">../</a></li>\n");
+  }
+  for (is_file, name) in entries {
+    let suffix = if is_file { "" } else { "/" };
+    let _ = writeln!(html, "<li><a href=\"{}{suffix}\">{}{suffix}</a></li>", encode_segment(&name), escape_html(&name));
+  }
+  html.push_str("</ul>\n</body>\n</html>\n");
+  Ok(html)
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&#39;")
+}
+
+/// Percent-encodes a file name for use as a relative URI path segment.
+fn encode_segment(name: &str) -> String {
+  name.bytes().fold(String::with_capacity(name.len()), |mut encoded, byte| {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+      _ => {
+        let _ = write!(encoded, "%{byte:02X}");
+      }
+    }
+    encoded
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use http_body_util::BodyExt;
+  use hyper::StatusCode;
+
+  use crate::config::static_files::StaticFiles;
+  use crate::service::listing::encode_segment;
+  use crate::service::listing::listing;
+
+  #[tokio::test]
+  async fn test_listing() {
+    let response = listing(Path::new("tests/fixtures/root"), "/", &StaticFiles::default());
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "text/html; charset=utf-8");
+
+    let html = String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+    assert!(html.contains("<title>Index of /</title>"));
+    assert!(!html.contains("../"));
+    let errors = html.find("href=\"errors/\"").unwrap();
+    let foo = html.find("href=\"foo/\"").unwrap();
+    let index = html.find("href=\"index.php\"").unwrap();
+    assert!(errors < foo && foo < index);
+  }
+
+  #[test]
+  fn test_listing_missing() {
+    let response = listing(Path::new("tests/fixtures/root/missing"), "/missing/", &StaticFiles::default());
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+  }
+
+  #[tokio::test]
+  async fn test_listing_sensitive() {
+    let directory = std::env::temp_dir().join(format!("pasir-listing-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    for name in ["composer.json", "config.php.bak", "style.css"] {
+      std::fs::write(directory.join(name), "").unwrap();
+    }
+
+    let response = listing(&directory, "/", &StaticFiles::default());
+    let html = String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+    assert!(html.contains("href=\"style.css\""));
+    assert!(!html.contains("composer.json"));
+    assert!(!html.contains("config.php.bak"));
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn test_encode_segment() {
+    assert_eq!(encode_segment("a b#?.txt"), "a%20b%23%3F.txt");
+    assert_eq!(encode_segment("é"), "%C3%A9");
+  }
+}
@@ -0,0 +1,79 @@
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Instant;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::combinators::MapFrame;
+use hyper::Request;
+use hyper::Response;
+use hyper::body::Body;
+use hyper::body::Frame;
+use tower::Layer;
+use tower::Service;
+
+use crate::config::route::ServedRoute;
+use crate::metrics::METRICS;
+
+type CountingBody<B> = MapFrame<B, fn(Frame<Bytes>) -> Frame<Bytes>>;
+
+/// Records the count and duration of requests by route and status, and the body bytes received and
+/// sent.
+#[derive(Clone, Default)]
+pub(crate) struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+  type Service = MetricsService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    MetricsService { inner }
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct MetricsService<S> {
+  inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MetricsService<S>
+where
+  S: Service<Request<CountingBody<ReqBody>>, Response = Response<ResBody>>,
+  S::Future: Send + 'static,
+  S::Error: Send + 'static,
+  ReqBody: Body<Data = Bytes>,
+  ResBody: Body<Data = Bytes> + Send + 'static,
+{
+  type Response = Response<CountingBody<ResBody>>;
+  type Error = S::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    let start = Instant::now();
+    let future = self.inner.call(req.map(|body| body.map_frame(count_received as fn(_) -> _)));
+
+    Box::pin(async move {
+      let response = future.await?;
+      let route = response.extensions().get::<ServedRoute>().and_then(|route| route.0);
+      METRICS.observe_request(route, response.status(), start.elapsed());
+      Ok(response.map(|body| body.map_frame(count_sent as fn(_) -> _)))
+    })
+  }
+}
+
+fn count_received(frame: Frame<Bytes>) -> Frame<Bytes> {
+  if let Some(data) = frame.data_ref() {
+    METRICS.received(data.len());
+  }
+  frame
+}
+
+fn count_sent(frame: Frame<Bytes>) -> Frame<Bytes> {
+  if let Some(data) = frame.data_ref() {
+    METRICS.sent(data.len());
+  }
+  frame
+}
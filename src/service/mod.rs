@@ -1,38 +1,46 @@
 use std::convert::Infallible;
+use std::path::Path;
 
 use bytes::Bytes;
-#[cfg(not(php_zend_max_execution_timers))]
-use http_body_util::BodyExt;
-#[cfg(not(php_zend_max_execution_timers))]
-use http_body_util::Empty;
 use http_body_util::combinators::UnsyncBoxBody;
-#[cfg(not(php_zend_max_execution_timers))]
-use hyper::Response;
-#[cfg(not(php_zend_max_execution_timers))]
-use tower::BoxError;
-#[cfg(not(php_zend_max_execution_timers))]
-use tower::timeout::error::Elapsed;
+use tower_http::services::ServeDir;
 
-#[cfg(not(php_zend_max_execution_timers))]
-use crate::util::response_ext::ResponseExt;
+use crate::config::static_files::Encoding;
+use crate::config::static_files::StaticFiles;
 
+mod access_log;
+mod cache;
+mod error;
+mod etag;
+pub(crate) mod health;
+mod listing;
+mod metrics;
 pub(crate) mod php;
+mod proxy;
 mod router;
 
+pub(crate) use access_log::AccessLogLayer;
+pub(crate) use cache::CacheLayer;
+pub(crate) use cache::CacheService;
+pub(crate) use error::ErrorPageLayer;
+pub(crate) use etag::EtagLayer;
+pub(crate) use metrics::MetricsLayer;
 pub(crate) use php::PhpService;
+pub(crate) use proxy::ProxyService;
 pub(crate) use router::RouterService;
 
 type ResponseBody = UnsyncBoxBody<Bytes, Infallible>;
-#[cfg(not(php_zend_max_execution_timers))]
-type MapResult = Result<Response<ResponseBody>, BoxError>;
 
-#[cfg(not(php_zend_max_execution_timers))]
-pub(crate) fn map_result(result: MapResult) -> MapResult {
-  result.or_else(|err| {
-    if err.is::<Elapsed>() {
-      return Ok(Response::gateway_timeout(Empty::default().boxed_unsync())?);
-    }
-
-    Err(err)
-  })
+pub(crate) fn serve_dir(root: impl AsRef<Path>, config: &StaticFiles) -> ServeDir {
+  config.precompressed().iter().fold(
+    ServeDir::new(root)
+      .call_fallback_on_method_not_allowed(true)
+      .append_index_html_on_directories(false),
+    |serve_dir, encoding| match encoding {
+      Encoding::Gzip => serve_dir.precompressed_gzip(),
+      Encoding::Br => serve_dir.precompressed_br(),
+      Encoding::Zstd => serve_dir.precompressed_zstd(),
+      Encoding::Deflate => serve_dir.precompressed_deflate(),
+    },
+  )
 }
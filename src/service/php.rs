@@ -2,10 +2,14 @@ use std::convert::Infallible;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::OnceLock;
 use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 use ext_php_rs::embed::PhpThreadGuard;
+use ext_php_rs::zend::ExecutorGlobals;
 use http_body_util::BodyExt;
 use http_body_util::Empty;
 use http_body_util::Full;
@@ -13,20 +17,64 @@ use http_body_util::combinators::UnsyncBoxBody;
 use hyper::Request;
 use hyper::Response;
 use hyper::body::Body;
+use hyper::http::HeaderValue;
 use pasir_sapi::context::ServerContext;
 use pasir_sapi::error::ExecutePhpError;
 use tower::Service;
+use tracing::Span;
+use tracing::debug;
+use tracing::debug_span;
 use tracing::error;
 
 use crate::cli::serve::Stream;
+use crate::config::error::DebugErrors;
+use crate::executions::EXECUTIONS;
+use crate::metrics::METRICS;
 use crate::sapi::context::Context;
 use crate::sapi::context::ContextSender;
+use crate::sapi::context::RequestVariables;
 use crate::sapi::context::ResponseType;
+use crate::sapi::context::SERVER_TIMING;
+use crate::sapi::context::ServerTiming;
+use crate::service::ResponseBody;
 use crate::util::response_ext::ResponseExt;
 
 #[derive(Clone, Default)]
 pub(crate) struct PhpService {}
 
+/// Script run for a response and how long PHP took to execute it, inserted in the response
+/// extensions and completed from the PHP thread.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PhpExecution {
+  script: Arc<OnceLock<String>>,
+  started: Arc<OnceLock<Instant>>,
+  duration: Arc<OnceLock<Duration>>,
+}
+
+impl PhpExecution {
+  pub(crate) fn script(&self) -> Option<&str> {
+    self.script.get().map(String::as_str)
+  }
+
+  /// Execution time of the script, or the time elapsed so far while it is still running.
+  pub(crate) fn duration(&self) -> Option<Duration> {
+    self.duration.get().copied().or_else(|| self.started.get().map(Instant::elapsed))
+  }
+}
+
+impl PhpService {
+  /// Responds to a failed execution, with the details of the PHP error when `debug_errors` is set.
+  fn error_response(error: ExecutePhpError, debug_errors: bool) -> Result<Response<ResponseBody>, Infallible> {
+    let error_body = Empty::default().boxed_unsync();
+    match error {
+      ExecutePhpError::InitSapiGlobalsError(_) => Response::bad_request(error_body),
+      ExecutePhpError::RequestStartupFailed => Response::service_unavailable(error_body),
+      ExecutePhpError::Bailout(Some(error)) if debug_errors => Ok(crate::service::error::debug_page(&error)),
+      ExecutePhpError::Bailout(_) => Response::internal_server_error(error_body),
+    }
+  }
+}
+
 impl<B> Service<Request<B>> for PhpService
 where
   B: Body + Send + 'static,
@@ -44,58 +92,166 @@ where
     let root = req.extensions().get::<Arc<PathBuf>>().unwrap().clone();
     let stream = req.extensions().get::<Arc<Stream>>().unwrap().clone();
     let error_body = Empty::default().boxed_unsync();
+    let execution = PhpExecution::default();
+    let timeout = max_execution_time(&req);
 
-    Box::pin(async move {
-      let (head, body) = req.into_parts();
+    let future = Box::pin(async move {
+      let (mut head, body) = req.into_parts();
       let bytes = match body.collect().await {
         Ok(collected) => collected.to_bytes(),
         Err(_) => return Response::internal_server_error(error_body),
       };
 
-      let (error_tx, error_rx) =
-        tokio::sync::oneshot::channel::<fn(error_body: UnsyncBoxBody<Bytes, Infallible>) -> Result<Self::Response, Infallible>>();
+      let (error_tx, error_rx) = tokio::sync::oneshot::channel::<ExecutePhpError>();
       let (head_rx, body_rx, context_tx) = ContextSender::receiver();
+      // Outcome of the execution with its `Server-Timing` header, awaited by full responses when needed.
+      let (done_tx, done_rx) = tokio::sync::oneshot::channel::<Result<Option<HeaderValue>, ExecutePhpError>>();
+      let server_timing = head.extensions.get::<ServerTiming>().cloned();
+      let debug_errors = head.extensions.get::<DebugErrors>().is_some();
+      let timed = server_timing.is_some();
+      let wait = timed || debug_errors;
+
+      let trace_context = crate::otel::trace_context();
+      if !trace_context.is_empty() {
+        let variables = head.extensions.get_or_insert_default::<RequestVariables>();
+        variables.server.extend(trace_context);
+      }
 
+      let queued = (METRICS.php_queued(), debug_span!("php_queue"));
+      let queued_at = Instant::now();
+      let span = Span::current();
+      let php_execution = execution.clone();
       tokio::task::spawn_blocking(move || {
+        drop(queued);
+        let queue = queued_at.elapsed();
+        let _span = span.enter();
+        METRICS.php_thread();
+        let _active = METRICS.php_active();
         let _guard = PhpThreadGuard::new();
         unsafe { pasir_sys::zend_update_current_locale() }
 
         let request = Request::from_parts(head, bytes);
+        let (method, uri) = (request.method().to_string(), request.uri().to_string());
         let context = Context::new(root.clone(), stream, request, context_tx);
+        let request_id = context.request_id().map(str::to_string);
         let script = root.join(context.script_name().trim_start_matches("/"));
-        if let Err(e) = context.execute_php(script, |err| {
-          error!("run_script failed: {:?}", err);
-        }) {
-          let callback = match e {
-            ExecutePhpError::InitSapiGlobalsError(_) => Response::bad_request,
-            ExecutePhpError::RequestStartupFailed => Response::service_unavailable,
-            ExecutePhpError::Bailout => Response::internal_server_error,
-          };
-          return error_tx.send(callback);
+        let vm_interrupt = &raw mut ExecutorGlobals::get_mut().vm_interrupt;
+        let _execution = EXECUTIONS.track(context.request_id(), method, uri, script.clone(), vm_interrupt);
+        let started = Instant::now();
+        let _ = php_execution.script.set(context.script_name().to_string());
+        let _ = php_execution.started.set(started);
+        let result = context.execute_php(script, |err| {
+          debug!("run_script failed: {:?}", err);
+        });
+        let _ = php_execution.duration.set(started.elapsed());
+        if let Err(e @ ExecutePhpError::Bailout(_)) = &result {
+          METRICS.php_bailout();
+          error!(request_id, script = php_execution.script(), "{e}");
+        }
+        if wait {
+          let done = result
+            .clone()
+            .map(|timings| server_timing.map(|server_timing| server_timing.header_value(queue, timings)));
+          let _ = done_tx.send(done);
+        }
+        if let Err(e) = result {
+          return error_tx.send(e);
         }
 
         Ok(())
       });
 
-      tokio::select! {
-        Ok(callback) = error_rx => {
-          callback(error_body)
+      let response = tokio::select! {
+        Ok(error) = error_rx => {
+          Self::error_response(error, debug_errors)
         }
         Ok(mut head) = head_rx => {
-          let response_type = head.extensions.get_or_insert_default::<ResponseType>();
-          let body = match response_type {
-            ResponseType::Full => Full::new(body_rx.collect().await.unwrap().to_bytes()).boxed_unsync(),
-            ResponseType::Chunked => body_rx.boxed_unsync(),
-          };
-          let response = Response::from_parts(head, body);
-          Ok(response)
+          let response_type = head.extensions.get_or_insert_default::<ResponseType>().clone();
+          match response_type {
+            ResponseType::Full => {
+              let body = Full::new(body_rx.collect().await.unwrap().to_bytes()).boxed_unsync();
+              // The head of a full response is sent within the request shutdown, which must end first.
+              let done = match wait {
+                true => done_rx.await.ok(),
+                false => None,
+              };
+              match done {
+                Some(Err(error)) if debug_errors => Self::error_response(error, debug_errors),
+                Some(Ok(Some(server_timing))) => {
+                  head.headers.append(&SERVER_TIMING, server_timing);
+                  Ok(Response::from_parts(head, body))
+                }
+                _ => Ok(Response::from_parts(head, body)),
+              }
+            }
+            ResponseType::Chunked => {
+              // The head was sent when the script flushed, before the timings were known.
+              if timed {
+                debug!("Server-Timing left out of a streamed response");
+              }
+              Ok(Response::from_parts(head, body_rx.boxed_unsync()))
+            }
+          }
         }
         else => Response::internal_server_error(error_body)
-      }
-    })
+      };
+      response.map(|mut response| {
+        response.extensions_mut().insert(execution);
+        response
+      })
+    });
+    with_timeout(timeout, future)
   }
 }
 
+/// `max_execution_time` of the request, from the INI entries of its route or site, or else from the
+/// PHP configuration.
+#[cfg(not(php_zend_max_execution_timers))]
+fn max_execution_time<B>(req: &Request<B>) -> Option<Duration> {
+  // Admin values are applied last, overriding the others.
+  let ini = req.extensions().get::<crate::sapi::context::IniEntries>();
+  let seconds = ini
+    .and_then(|ini| ini.admin_values.get("max_execution_time").or(ini.values.get("max_execution_time")))
+    .and_then(|seconds| seconds.parse::<i64>().ok())
+    .unwrap_or_else(|| ExecutorGlobals::get().timeout_seconds);
+  (seconds > 0).then(|| Duration::from_secs(seconds.cast_unsigned()))
+}
+
+/// Answers `504 Gateway Timeout` once `timeout` elapses, as PHP builds without Zend max execution
+/// timers cannot enforce `max_execution_time` on their own. The script keeps running until it ends.
+#[cfg(not(php_zend_max_execution_timers))]
+fn with_timeout<F>(timeout: Option<Duration>, future: Pin<Box<F>>) -> Pin<Box<dyn Future<Output = F::Output> + Send>>
+where
+  F: Future<Output = Result<Response<ResponseBody>, Infallible>> + Send + 'static,
+{
+  let Some(timeout) = timeout else {
+    return future;
+  };
+  Box::pin(async move {
+    match tokio::time::timeout(timeout, future).await {
+      Ok(response) => response,
+      Err(_) => {
+        METRICS.timeout();
+        Response::gateway_timeout(Empty::default().boxed_unsync())
+      }
+    }
+  })
+}
+
+/// PHP enforces `max_execution_time` with Zend max execution timers.
+#[cfg(php_zend_max_execution_timers)]
+fn max_execution_time<B>(_req: &Request<B>) -> Option<Duration> {
+  None
+}
+
+#[cfg(php_zend_max_execution_timers)]
+fn with_timeout<F>(_timeout: Option<Duration>, future: Pin<Box<F>>) -> Pin<Box<dyn Future<Output = F::Output> + Send>>
+where
+  F: Future<Output = Result<Response<ResponseBody>, Infallible>> + Send + 'static,
+{
+  future
+}
+
 #[cfg(test)]
 mod tests {
   use std::path::PathBuf;
@@ -112,6 +268,8 @@ mod tests {
 
   use crate::cli::serve::Stream;
   use crate::sapi::Sapi;
+  use crate::sapi::context::SERVER_TIMING;
+  use crate::sapi::context::ServerTiming;
   use crate::service::PhpService;
 
   struct SapiTestGuard(*mut SapiModule);
@@ -157,5 +315,18 @@ mod tests {
       let body = response.into_body().collect().await.unwrap().to_bytes();
       assert!(!body.is_empty(), "request {i} returned an empty body");
     }
+
+    // Only buffered responses get a `Server-Timing` header, streamed ones are sent before it is known.
+    for (uri, timed) in [("/index.php", true), ("/flush.php", false)] {
+      let mut request = request.clone();
+      *request.uri_mut() = uri.parse().unwrap();
+      request.extensions_mut().insert(ServerTiming::default());
+      let response = service.call(request).await.unwrap();
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers().contains_key(&SERVER_TIMING), timed, "{uri}");
+      if !timed {
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "foobar");
+      }
+    }
   }
 }
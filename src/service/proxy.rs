@@ -0,0 +1,269 @@
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use hyper::HeaderMap;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::Version;
+use hyper::body::Body;
+use hyper::body::Frame;
+use hyper::body::Incoming;
+use hyper::body::SizeHint;
+use hyper::header::CONNECTION;
+use hyper::header::HOST;
+use hyper::header::UPGRADE;
+use hyper::http::HeaderName;
+use hyper::http::HeaderValue;
+use hyper::http::request::Parts;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+use tower::BoxError;
+use tower::Service;
+use tracing::debug;
+use tracing::error;
+
+use crate::cli::serve::Stream;
+use crate::config::upstream::Upstreams;
+use crate::service::ResponseBody;
+use crate::util::response_ext::ResponseExt;
+
+type ProxyRequestBody = http_body_util::combinators::UnsyncBoxBody<Bytes, BoxError>;
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Headers that only apply to a single connection and must not be forwarded.
+static HOP_BY_HOP_HEADERS: [&str; 7] = [
+  "keep-alive",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "proxy-connection",
+  "te",
+  "trailer",
+  "transfer-encoding",
+];
+
+#[derive(Clone)]
+pub(crate) struct ProxyService {
+  client: Client<HttpConnector, ProxyRequestBody>,
+}
+
+impl Default for ProxyService {
+  fn default() -> Self {
+    Self {
+      client: Client::builder(TokioExecutor::new()).build_http(),
+    }
+  }
+}
+
+impl ProxyService {
+  fn forward_headers(parts: &mut Parts, stream: &Stream) {
+    let upgrade = parts.headers.get(UPGRADE).cloned();
+    remove_hop_by_hop_headers(&mut parts.headers);
+    if let Some(upgrade) = upgrade {
+      parts.headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+      parts.headers.insert(UPGRADE, upgrade);
+    }
+
+    if !parts.headers.contains_key(HOST)
+      && let Some(authority) = parts.uri.authority()
+      && let Ok(host) = HeaderValue::from_str(authority.as_str())
+    {
+      parts.headers.insert(HOST, host);
+    }
+    if let Some(host) = parts.headers.get(HOST).cloned() {
+      parts.headers.insert(&X_FORWARDED_HOST, host);
+    }
+
+    let client_ip = stream.peer_addr().ip().to_string();
+    let forwarded_for = match parts.headers.get(&X_FORWARDED_FOR).and_then(|value| value.to_str().ok()) {
+      None => client_ip,
+      Some(forwarded_for) => format!("{forwarded_for}, {client_ip}"),
+    };
+    if let Ok(forwarded_for) = HeaderValue::from_str(&forwarded_for) {
+      parts.headers.insert(&X_FORWARDED_FOR, forwarded_for);
+    }
+    // Pasir only listens for plain HTTP, whatever scheme the client claims.
+    parts.headers.insert(&X_FORWARDED_PROTO, HeaderValue::from_static("http"));
+  }
+
+  /// Splices the client and upstream connections together once both sides switched protocols.
+  fn tunnel<B>(mut req: Request<B>, response: &mut Response<Incoming>) {
+    let client = hyper::upgrade::on(&mut req);
+    let upstream = hyper::upgrade::on(response);
+    tokio::spawn(async move {
+      match tokio::try_join!(client, upstream) {
+        Ok((client, upstream)) => {
+          let result = tokio::io::copy_bidirectional(&mut TokioIo::new(client), &mut TokioIo::new(upstream)).await;
+          if let Err(err) = result {
+            debug!("Upgraded connection closed: {err}");
+          }
+        }
+        Err(err) => error!("Connection upgrade failed: {err}"),
+      }
+    });
+  }
+}
+
+impl<B> Service<Request<B>> for ProxyService
+where
+  B: Body<Data = Bytes> + Send + 'static,
+  B::Error: Into<BoxError>,
+{
+  type Response = Response<ResponseBody>;
+  type Error = Infallible;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, mut req: Request<B>) -> Self::Future {
+    let upstreams = req.extensions().get::<Upstreams>().unwrap().clone();
+    let stream = req.extensions().get::<Arc<Stream>>().unwrap().clone();
+    let client = self.client.clone();
+
+    // Keep the original request around to receive the client side of a protocol upgrade.
+    let upgrade = req.headers().contains_key(UPGRADE).then(|| {
+      let mut upgrade = Request::new(());
+      *upgrade.extensions_mut() = std::mem::take(req.extensions_mut());
+      upgrade
+    });
+
+    Box::pin(async move {
+      let (mut parts, body) = req.into_parts();
+      let (index, uri) = upstreams.select(&parts.uri);
+      Self::forward_headers(&mut parts, &stream);
+      parts.uri = uri;
+      parts.version = Version::HTTP_11;
+
+      let request = Request::from_parts(parts, body.map_err(Into::into).boxed_unsync());
+      match client.request(request).await {
+        Ok(mut response) => {
+          // A gateway error from the upstream counts as a failure, like a connection error.
+          let failed = matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+          );
+          upstreams.report(index, !failed);
+          if response.status() == StatusCode::SWITCHING_PROTOCOLS
+            && let Some(upgrade) = upgrade
+          {
+            Self::tunnel(upgrade, &mut response);
+            return Ok(response.map(|_| Empty::default().boxed_unsync()));
+          }
+
+          remove_hop_by_hop_headers(response.headers_mut());
+          Ok(response.map(|body| UpstreamBody(body).boxed_unsync()))
+        }
+        Err(err) => {
+          error!("Proxy request to upstream failed: {err}");
+          upstreams.report(index, false);
+          Response::bad_gateway(Empty::default().boxed_unsync())
+        }
+      }
+    })
+  }
+}
+
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+  let connection = headers
+    .get_all(CONNECTION)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+    .collect::<Vec<HeaderName>>();
+  for name in connection {
+    headers.remove(name);
+  }
+  for name in HOP_BY_HOP_HEADERS {
+    headers.remove(name);
+  }
+  headers.remove(CONNECTION);
+  headers.remove(UPGRADE);
+}
+
+/// Upstream response body, ending the stream early when the upstream connection fails mid-response.
+struct UpstreamBody(Incoming);
+
+impl Body for UpstreamBody {
+  type Data = Bytes;
+  type Error = Infallible;
+
+  fn poll_frame(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    Pin::new(&mut self.0).poll_frame(cx).map(|frame| match frame {
+      Some(Ok(frame)) => Some(Ok(frame)),
+      Some(Err(err)) => {
+        error!("Failed to read upstream response body: {err}");
+        None
+      }
+      None => None,
+    })
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.0.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.0.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::HeaderMap;
+  use hyper::Request;
+  use hyper::header::CONNECTION;
+  use hyper::header::HOST;
+  use hyper::header::UPGRADE;
+
+  use crate::cli::serve::Stream;
+  use crate::service::proxy::ProxyService;
+  use crate::service::proxy::remove_hop_by_hop_headers;
+
+  #[test]
+  fn test_remove_hop_by_hop_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONNECTION, "keep-alive, x-foo".parse().unwrap());
+    headers.insert("keep-alive", "timeout=5".parse().unwrap());
+    headers.insert("x-foo", "bar".parse().unwrap());
+    headers.insert("x-bar", "baz".parse().unwrap());
+
+    remove_hop_by_hop_headers(&mut headers);
+    assert_eq!(headers.len(), 1);
+    assert!(headers.contains_key("x-bar"));
+  }
+
+  #[test]
+  fn test_forward_headers() {
+    let request = Request::builder()
+      .uri("/foo")
+      .header(HOST, "example.com")
+      .header(CONNECTION, "Upgrade")
+      .header(UPGRADE, "websocket")
+      .header("X-Forwarded-For", "10.0.0.1")
+      .header("X-Forwarded-Proto", "https")
+      .body(())
+      .unwrap();
+    let (mut parts, _) = request.into_parts();
+
+    ProxyService::forward_headers(&mut parts, &Stream::default());
+    assert_eq!(parts.headers["host"], "example.com");
+    assert_eq!(parts.headers["connection"], "upgrade");
+    assert_eq!(parts.headers["upgrade"], "websocket");
+    assert_eq!(parts.headers["x-forwarded-for"], "10.0.0.1, 127.0.0.1");
+    assert_eq!(parts.headers["x-forwarded-host"], "example.com");
+    assert_eq!(parts.headers["x-forwarded-proto"], "http");
+  }
+}
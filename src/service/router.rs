@@ -1,89 +1,400 @@
 use std::convert::Infallible;
+use std::path::Path;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Poll;
 
+use bytes::Bytes;
 use http_body_util::BodyExt;
 use hyper::Request;
 use hyper::Response;
+use hyper::StatusCode;
+use hyper::Uri;
 use hyper::body::Body;
+use tower::BoxError;
 use tower::Service;
 use tower_http::services::ServeDir;
 use tower_http::services::fs::ServeFileSystemResponseBody;
+use tracing::Span;
+use tracing::debug_span;
+use tracing::field;
 
+use crate::config::access::CredentialCheck;
+use crate::config::compression::NoCompression;
+use crate::config::cors::Cors;
+use crate::config::error::DebugErrors;
+use crate::config::rate_limit::RateLimitStatus;
 use crate::config::route::ApplyActions;
+use crate::config::route::ApplyRequestActions;
+use crate::config::route::ResponseHeaderAction;
 use crate::config::route::RouteServe;
 use crate::config::route::Routes;
+use crate::config::route::ServedRoute;
+use crate::config::route::TryFiles;
+use crate::config::route::TryFilesTarget;
+use crate::config::route::join_root;
+use crate::config::static_files::StaticFiles;
+use crate::config::static_files::percent_decode;
+use crate::sapi::context::ScriptName;
+use crate::sapi::context::ServerTiming;
+use crate::service::CacheService;
 use crate::service::ResponseBody;
+use crate::service::listing::listing;
 use crate::service::php::PhpService;
+use crate::service::proxy::ProxyService;
+
+pub(crate) type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<ResponseBody>, Infallible>> + Send>>;
+
+/// How a directory request is served, once looked up on disk.
+enum DirectoryTarget {
+  Index(TryFilesTarget),
+  Listing(Response<ResponseBody>),
+  Fallback,
+}
 
 #[derive(Clone)]
 pub(crate) struct RouterService {
   inner: ServeDir,
-  php: PhpService,
+  php: CacheService<PhpService>,
+  proxy: ProxyService,
 }
 
 impl RouterService {
-  pub(crate) fn new(inner: ServeDir, php: PhpService) -> Self {
-    Self { inner, php }
+  pub(crate) fn new(inner: ServeDir, php: CacheService<PhpService>, proxy: ProxyService) -> Self {
+    Self { inner, php, proxy }
   }
 
-  fn fallback(&self) -> ServeDir<PhpService> {
-    self.inner.clone().fallback(self.php.clone())
+  fn fallback(&self, serve_dir: ServeDir) -> ServeDir<CacheService<PhpService>> {
+    serve_dir.fallback(self.php.clone())
   }
 
-  fn map_serve_dir_response(response: Response<ServeFileSystemResponseBody>) -> Response<ResponseBody> {
-    response.map(|body| body.map_err(|_| unreachable!()).boxed_unsync())
+  fn try_files<B>(
+    &mut self,
+    mut req: Request<B>,
+    serve_dir: ServeDir,
+    target: TryFilesTarget,
+    static_files: Arc<StaticFiles>,
+  ) -> <Self as Service<Request<B>>>::Future
+  where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+  {
+    match target {
+      TryFilesTarget::Php(script_name) => {
+        req.extensions_mut().insert(ScriptName(script_name));
+        self.php.call(req)
+      }
+      TryFilesTarget::Static(path) => {
+        *req.uri_mut() = Self::rewrite_uri(req.uri(), &path);
+        Self::serve_static(serve_dir, req, static_files)
+      }
+      TryFilesTarget::Status(status) => Box::pin(async move {
+        let mut response = Response::<ResponseBody>::default();
+        *response.status_mut() = status;
+        Ok(response)
+      }),
+    }
   }
-}
 
-impl<B> Service<Request<B>> for RouterService
-where
-  B: Body + Send + 'static,
-  B::Data: Send,
-{
-  type Response = Response<ResponseBody>;
-  type Error = Infallible;
-  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+  /// Serves a directory request from its index file or listing, falling back to PHP or `ServeDir`.
+  fn serve_directory<B>(&mut self, req: Request<B>, serve_dir: ServeDir, static_files: Arc<StaticFiles>, php: bool) -> ResponseFuture
+  where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+  {
+    let root = req.extensions().get::<Arc<PathBuf>>().unwrap().clone();
+    let path = req.uri().path().to_string();
+    let lookup_files = static_files.clone();
+    let mut router = self.clone();
 
-  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-    <PhpService as Service<Request<B>>>::poll_ready(&mut self.php, cx)
+    Box::pin(async move {
+      // The directory is read on the blocking pool rather than on the runtime workers.
+      let target = tokio::task::spawn_blocking(move || Self::lookup_directory(&root, &path, &lookup_files, php))
+        .await
+        .unwrap_or(DirectoryTarget::Fallback);
+      match target {
+        DirectoryTarget::Index(target) => router.try_files(req, serve_dir, target, static_files).await,
+        DirectoryTarget::Listing(response) => Ok(response),
+        DirectoryTarget::Fallback if php => router.php.call(req).await,
+        DirectoryTarget::Fallback => Self::serve_static(serve_dir, req, static_files).await,
+      }
+    })
   }
 
-  fn call(&mut self, req: Request<B>) -> Self::Future {
-    let routes = req.extensions().get::<Arc<Routes>>().unwrap().clone();
-    if let Some(mut served_route) = routes.served_route(&req) {
+  /// Serves the first `try_files` entry found on disk, probed on the blocking pool.
+  fn serve_try_files<B>(
+    &mut self,
+    req: Request<B>,
+    serve_dir: ServeDir,
+    try_files: TryFiles,
+    static_files: Arc<StaticFiles>,
+  ) -> ResponseFuture
+  where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+  {
+    let root = req.extensions().get::<Arc<PathBuf>>().unwrap().clone();
+    let path = req.uri().path().to_string();
+    let mut router = self.clone();
+
+    Box::pin(async move {
+      let target = tokio::task::spawn_blocking(move || try_files.resolve(&root, &path))
+        .await
+        .unwrap_or(TryFilesTarget::Status(StatusCode::INTERNAL_SERVER_ERROR));
+      router.try_files(req, serve_dir, target, static_files).await
+    })
+  }
+
+  fn lookup_directory(root: &Path, path: &str, static_files: &StaticFiles, php: bool) -> DirectoryTarget {
+    if let Some(target) = static_files.index(root, path)
+      && (php || matches!(target, TryFilesTarget::Static(_)))
+    {
+      return DirectoryTarget::Index(target);
+    }
+    if static_files.list_directories()
+      && let Some(directory) = join_root(root, &percent_decode(path))
+      && directory.is_dir()
+      && !(php && directory.join("index.php").is_file())
+    {
+      return DirectoryTarget::Listing(listing(&directory, path, static_files));
+    }
+    DirectoryTarget::Fallback
+  }
+
+  fn serve_static<B>(mut serve_dir: ServeDir, req: Request<B>, static_files: Arc<StaticFiles>) -> ResponseFuture
+  where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+  {
+    let path = req.uri().path().to_string();
+    let future = serve_dir.call(req);
+    Box::pin(async move {
+      future.await.map(|mut response| {
+        static_files.decorate(&path, &mut response);
+        Self::map_serve_dir_response(response)
+      })
+    })
+  }
+
+  /// Points the URI at the decoded `path`, encoding it again for `ServeDir`.
+  fn rewrite_uri(uri: &Uri, path: &str) -> Uri {
+    let path = percent_encode(path);
+    let path_and_query = match uri.query() {
+      None => path,
+      Some(query) => format!("{path}?{query}"),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+  }
+
+  /// Serves the request once the credentials it carries were verified.
+  fn dispatch<B>(
+    &mut self,
+    mut req: Request<B>,
+    routes: Arc<Routes>,
+    serve_dir: ServeDir,
+    cors: Option<ResponseHeaderAction>,
+    rate_limit: Option<RateLimitStatus>,
+    routing: Span,
+  ) -> ResponseFuture
+  where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+  {
+    let routing = routing.entered();
+    let static_files = routes.static_files().clone();
+    if let Err(denied) = routes.authorize(&mut req) {
+      return Self::respond(denied.into_response(), cors);
+    }
+    if let Some(response) = self.php.purge(&req) {
+      return Self::respond(response, cors);
+    }
+    let compress = routes.compress(&req);
+    let decorate = move |response: &mut Response<ResponseBody>| {
+      if !compress {
+        response.extensions_mut().insert(NoCompression);
+      }
+      if let Some(status) = &rate_limit {
+        status.apply_actions(response);
+      }
+      if let Some(cors) = &cors {
+        cors.apply_actions(response);
+      }
+    };
+
+    let served_route = routes.served_route(&req);
+    routes.apply_request_actions(&mut req);
+    if let Some((index, _)) = &served_route {
+      routing.record("route", index);
+    }
+    drop(routing);
+
+    if let Some((index, mut served_route)) = served_route {
       let future = match served_route.serve() {
         RouteServe::Php => self.php.call(req),
         RouteServe::Default => Box::pin(async move { Ok(Response::default()) }),
-        RouteServe::Static => {
-          let future = self.inner.call(req);
-          Box::pin(async move { future.await.map(Self::map_serve_dir_response) })
+        RouteServe::Static if req.uri().path().ends_with('/') => self.serve_directory(req, serve_dir, static_files, false),
+        RouteServe::Static => Self::serve_static(serve_dir, req, static_files),
+        RouteServe::TryFiles => {
+          let try_files = served_route.try_files().unwrap().clone();
+          self.serve_try_files(req, serve_dir, try_files, static_files)
+        }
+        RouteServe::Proxy => {
+          req.extensions_mut().insert(served_route.upstreams().unwrap().clone());
+          self.proxy.call(req)
+        }
+        RouteServe::Status => {
+          let response = crate::status::respond(&req).map(BodyExt::boxed_unsync);
+          Box::pin(async move { Ok(response) })
         }
       };
 
       return Box::pin(async move {
         future.await.map(|mut response| {
           served_route.apply_actions(&mut response);
+          decorate(&mut response);
+          response.extensions_mut().insert(ServedRoute(Some(index)));
           response
         })
       });
     }
 
     let path = req.uri().path();
-    let future = match path.ends_with("/") || path.ends_with(".php") {
-      true => self.php.call(req),
-      false => {
-        let future = self.fallback().call(req);
-        Box::pin(async move { future.await.map(Self::map_serve_dir_response) })
-      }
+    let future = if path.ends_with("/") {
+      self.serve_directory(req, serve_dir, static_files, true)
+    } else if path.ends_with(".php") {
+      self.php.call(req)
+    } else {
+      let file = join_root(req.extensions().get::<Arc<PathBuf>>().unwrap(), &percent_decode(path));
+      let path = path.to_string();
+      let mut fallback = self.fallback(serve_dir);
+      Box::pin(async move {
+        let is_file = tokio::task::spawn_blocking(move || file.is_some_and(|file| file.is_file()))
+          .await
+          .unwrap_or(false);
+        fallback.call(req).await.map(|mut response| {
+          if is_file {
+            static_files.decorate(&path, &mut response);
+          }
+          Self::map_serve_dir_response(response)
+        })
+      })
     };
 
     Box::pin(async move {
       future.await.map(|mut response| {
         routes.apply_actions(&mut response);
+        decorate(&mut response);
+        response.extensions_mut().insert(ServedRoute(None));
         response
       })
     })
   }
+
+  fn respond(mut response: Response<ResponseBody>, cors: Option<ResponseHeaderAction>) -> ResponseFuture {
+    if let Some(cors) = cors {
+      cors.apply_actions(&mut response);
+    }
+    Box::pin(async move { Ok(response) })
+  }
+
+  fn map_serve_dir_response(response: Response<ServeFileSystemResponseBody>) -> Response<ResponseBody> {
+    response.map(|body| body.map_err(|_| unreachable!()).boxed_unsync())
+  }
+}
+
+impl<B> Service<Request<B>> for RouterService
+where
+  B: Body<Data = Bytes> + Send + 'static,
+  B::Error: Into<BoxError>,
+{
+  type Response = Response<ResponseBody>;
+  type Error = Infallible;
+  type Future = ResponseFuture;
+
+  fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+    <CacheService<PhpService> as Service<Request<B>>>::poll_ready(&mut self.php, cx)
+  }
+
+  fn call(&mut self, mut req: Request<B>) -> Self::Future {
+    let routing = debug_span!("routing", route = field::Empty).entered();
+    let mut routes = req.extensions().get::<Arc<Routes>>().unwrap().clone();
+    if let Some(probe) = routes.health().probe(req.uri().path()) {
+      return crate::service::health::respond(probe, routes.health().clone(), &req);
+    }
+    if routes.server_timing(&req) {
+      req.extensions_mut().insert(ServerTiming::default());
+    }
+    if routes.debug_errors(&req) {
+      req.extensions_mut().insert(DebugErrors);
+    }
+
+    let mut serve_dir = self.inner.clone();
+    if let Some(site) = routes.clone().resolve_site(&mut req) {
+      serve_dir = crate::service::serve_dir(site.root(), site.routes().static_files());
+      routes = req.extensions().get::<Arc<Routes>>().unwrap().clone();
+    }
+
+    let static_files = routes.static_files().clone();
+    if static_files.is_sensitive(req.uri().path()) {
+      let mut response = Response::<ResponseBody>::default();
+      *response.status_mut() = StatusCode::NOT_FOUND;
+      return Box::pin(async move { Ok(response) });
+    }
+
+    let cors = match routes.cors(&req) {
+      Some(cors) if Cors::is_preflight(&req) => {
+        let response = cors.preflight::<_, ResponseBody>(&req);
+        return Box::pin(async move { Ok(response) });
+      }
+      cors => cors.map(|cors| cors.response_headers(&req)),
+    };
+    let rate_limit = match routes.rate_limit(&req) {
+      Ok(status) => status,
+      Err(exceeded) => return Self::respond(exceeded.into_response(), cors),
+    };
+
+    let checks = routes.credential_checks(&req);
+    if checks.is_empty() {
+      return self.dispatch(req, routes, serve_dir, cors, rate_limit, routing.exit());
+    }
+    let routing = routing.exit();
+    let mut router = self.clone();
+    Box::pin(async move {
+      // Each bcrypt check takes milliseconds of CPU, too long to hold a runtime worker for.
+      let _ = tokio::task::spawn_blocking(move || checks.into_iter().for_each(CredentialCheck::run)).await;
+      router.dispatch(req, routes, serve_dir, cors, rate_limit, routing).await
+    })
+  }
+}
+
+/// Percent-encodes the bytes of `path` that are not allowed in a URI path.
+fn percent_encode(path: &str) -> String {
+  let mut encoded = String::with_capacity(path.len());
+  for byte in path.bytes() {
+    match byte.is_ascii_alphanumeric() || b"-._~/!$&'()*+,;=:@".contains(&byte) {
+      true => encoded.push(byte as char),
+      false => encoded.push_str(&format!("%{byte:02X}")),
+    }
+  }
+  encoded
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::Uri;
+  use rstest::rstest;
+
+  use crate::service::router::RouterService;
+
+  #[rstest]
+  #[case("/foo?bar=1", "/foo.html", "/foo.html?bar=1")]
+  #[case("/caf%C3%A9", "/café.html", "/caf%C3%A9.html")]
+  #[case("/a%20b", "/a b/100%.html", "/a%20b/100%25.html")]
+  fn test_rewrite_uri(#[case] uri: &str, #[case] path: &str, #[case] expected: &str) {
+    let uri = uri.parse::<Uri>().unwrap();
+    assert_eq!(RouterService::rewrite_uri(&uri, path).to_string(), expected);
+  }
 }
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::executions::EXECUTIONS;
+use crate::executions::Execution;
+use crate::executions::Executions;
+use crate::metrics::METRICS;
+use crate::util::log_file::LogFile;
+use crate::util::log_file::rfc3339_time;
+
+/// Slow request log, enabled with `--slowlog`.
+pub(crate) static SLOWLOG: OnceLock<Slowlog> = OnceLock::new();
+
+/// Logs the backtrace of the PHP executions running longer than `timeout`, like
+/// `request_slowlog_timeout` of php-fpm.
+pub(crate) struct Slowlog {
+  timeout: Duration,
+  file: LogFile,
+}
+
+impl Slowlog {
+  pub(crate) fn new(timeout: Duration, file: LogFile) -> Self {
+    Self { timeout, file }
+  }
+
+  /// Checks the in-flight executions until the process exits.
+  pub(crate) async fn watch(&self) {
+    let mut interval = tokio::time::interval((self.timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(1)));
+    loop {
+      interval.tick().await;
+      self.check(&EXECUTIONS);
+    }
+  }
+
+  /// Requests the backtrace of the executions that exceeded the timeout, once each. PHP threads log
+  /// it from `zend_interrupt_function`, as their frames cannot be read from another thread.
+  fn check(&self, executions: &Executions) {
+    for execution in executions.lock().values_mut() {
+      if execution.slow || execution.started.elapsed() < self.timeout {
+        continue;
+      }
+      execution.slow = true;
+      METRICS.slow_request();
+      execution.request_backtrace();
+    }
+  }
+
+  /// Logs a slow execution with its backtrace, innermost frame first.
+  pub(crate) fn log(&self, execution: &Execution, backtrace: &[String]) {
+    let mut entry = format!(
+      "[{}] request_id={} elapsed={:.3}s\nrequest = {} {}\nscript_filename = {}\n",
+      rfc3339_time(SystemTime::now()),
+      execution.request_id.as_deref().unwrap_or("-"),
+      execution.started.elapsed().as_secs_f64(),
+      execution.method,
+      execution.uri,
+      execution.script.display(),
+    );
+    for frame in backtrace {
+      let _ = writeln!(entry, "{frame}");
+    }
+    entry.push('\n');
+    self.file.write(entry);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::time::Duration;
+
+  use crate::executions::Executions;
+  use crate::slowlog::Slowlog;
+  use crate::util::log_file::LogFile;
+
+  #[test]
+  fn test_check() {
+    let (file, _rx) = LogFile::channel();
+    let slowlog = Slowlog::new(Duration::from_millis(50), file);
+    let executions = Executions::default();
+
+    let guard = executions.track(
+      Some("f81d4fae"),
+      "GET".to_string(),
+      "/slow.php?foo=bar".to_string(),
+      PathBuf::from("/srv/www/slow.php"),
+      std::ptr::null_mut(),
+    );
+    slowlog.check(&executions);
+    assert!(!executions.lock().values().any(|execution| execution.slow));
+
+    std::thread::sleep(Duration::from_millis(60));
+    slowlog.check(&executions);
+    assert!(executions.lock().values().all(|execution| execution.slow));
+    drop(guard);
+  }
+
+  #[test]
+  fn test_log() {
+    let (file, mut rx) = LogFile::channel();
+    let slowlog = Slowlog::new(Duration::from_millis(50), file);
+    let executions = Executions::default();
+
+    let _guard = executions.track(
+      Some("f81d4fae"),
+      "GET".to_string(),
+      "/slow.php?foo=bar".to_string(),
+      PathBuf::from("/srv/www/slow.php"),
+      std::ptr::null_mut(),
+    );
+    let backtrace = ["sleep() /srv/www/slow.php:3".to_string(), "{main} /srv/www/slow.php:3".to_string()];
+    slowlog.log(executions.lock().values().next().unwrap(), &backtrace);
+    let entry = rx.try_recv().unwrap();
+    assert!(entry.contains("] request_id=f81d4fae elapsed=0."));
+    assert!(entry.ends_with(
+      "\nrequest = GET /slow.php?foo=bar\nscript_filename = /srv/www/slow.php\nsleep() /srv/www/slow.php:3\n{main} /srv/www/slow.php:3\n\n"
+    ));
+  }
+}
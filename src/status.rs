@@ -0,0 +1,394 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::header::CACHE_CONTROL;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use serde::Serialize;
+
+use crate::executions::EXECUTIONS;
+use crate::metrics::METRICS;
+use crate::metrics::Snapshot;
+use crate::util::log_file::clf_time;
+use crate::util::log_file::rfc3339_time;
+
+/// Time the server started at, reported as the uptime on the status route.
+static STARTED: OnceLock<(SystemTime, Instant)> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+  Text,
+  Json,
+  OpenMetrics,
+}
+
+/// Server status for routes served with `status`, like `pm.status_path` of php-fpm.
+#[derive(Debug)]
+struct Status {
+  start_time: SystemTime,
+  start_since: Duration,
+  metrics: Snapshot,
+  /// In-flight PHP executions, listed with `?full`.
+  requests: Option<Vec<InFlightRequest>>,
+}
+
+#[derive(Debug)]
+struct InFlightRequest {
+  request_id: Option<String>,
+  method: String,
+  uri: String,
+  script: String,
+  start_time: SystemTime,
+  duration: Duration,
+  memory: u64,
+}
+
+#[derive(Serialize)]
+struct JsonStatus<'a> {
+  start_time: String,
+  start_since: u64,
+  accepted_connections: u64,
+  php_queue: u64,
+  max_php_queue: u64,
+  idle_php_threads: u64,
+  active_php_threads: u64,
+  total_php_threads: u64,
+  max_active_php_threads: u64,
+  slow_requests: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  requests: Option<Vec<JsonRequest<'a>>>,
+}
+
+#[derive(Serialize)]
+struct JsonRequest<'a> {
+  request_id: Option<&'a str>,
+  method: &'a str,
+  uri: &'a str,
+  script: &'a str,
+  start_time: String,
+  duration: f64,
+  memory: u64,
+}
+
+/// Records the start of the server.
+pub(crate) fn start() {
+  STARTED.get_or_init(|| (SystemTime::now(), Instant::now()));
+}
+
+/// Responds with the status, as text, `?json` or `?openmetrics`, including the in-flight requests
+/// with `?full`.
+pub(crate) fn respond<B>(request: &Request<B>) -> Response<Full<Bytes>> {
+  if !matches!(*request.method(), Method::GET | Method::HEAD) {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+    return response;
+  }
+
+  let (format, full) = parse_query(request.uri().query());
+  let status = Status::collect(full);
+  let (body, content_type) = match format {
+    Format::Text => (status.text(), "text/plain; charset=utf-8"),
+    Format::Json => (status.json(), "application/json"),
+    Format::OpenMetrics => (status.openmetrics(), "application/openmetrics-text; version=1.0.0; charset=utf-8"),
+  };
+
+  let mut response = Response::new(Full::from(body));
+  let headers = response.headers_mut();
+  headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+  headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+  response
+}
+
+fn parse_query(query: Option<&str>) -> (Format, bool) {
+  let mut format = Format::Text;
+  let mut full = false;
+  for pair in query.unwrap_or_default().split('&') {
+    match pair.split_once('=').map_or(pair, |(key, _)| key) {
+      "json" => format = Format::Json,
+      "openmetrics" => format = Format::OpenMetrics,
+      "full" => full = true,
+      _ => {}
+    }
+  }
+  (format, full)
+}
+
+impl Status {
+  fn collect(full: bool) -> Self {
+    let (start_time, started) = *STARTED.get_or_init(|| (SystemTime::now(), Instant::now()));
+    let requests = full.then(|| {
+      let now = SystemTime::now();
+      EXECUTIONS
+        .lock()
+        .values()
+        .map(|execution| {
+          let duration = execution.started.elapsed();
+          InFlightRequest {
+            request_id: execution.request_id.clone(),
+            method: execution.method.clone(),
+            uri: execution.uri.clone(),
+            script: execution.script.display().to_string(),
+            start_time: now - duration,
+            duration,
+            memory: execution.memory(),
+          }
+        })
+        .collect()
+    });
+
+    Self {
+      start_time,
+      start_since: started.elapsed(),
+      metrics: METRICS.snapshot(),
+      requests,
+    }
+  }
+
+  fn idle_php_threads(&self) -> u64 {
+    self.metrics.php_threads.saturating_sub(self.metrics.php_active)
+  }
+
+  /// Formats the status the way php-fpm does, durations being in microseconds.
+  fn text(&self) -> String {
+    let metrics = &self.metrics;
+    let mut output = String::new();
+    let fields = [
+      ("start time", clf_time(self.start_time)),
+      ("start since", self.start_since.as_secs().to_string()),
+      ("accepted conn", metrics.accepted_connections.to_string()),
+      ("php queue", metrics.php_queued.to_string()),
+      ("max php queue", metrics.php_max_queued.to_string()),
+      ("idle php threads", self.idle_php_threads().to_string()),
+      ("active php threads", metrics.php_active.to_string()),
+      ("total php threads", metrics.php_threads.to_string()),
+      ("max active php threads", metrics.php_max_active.to_string()),
+      ("slow requests", metrics.slow_requests.to_string()),
+    ];
+    text_fields(&mut output, &fields);
+
+    for request in self.requests.iter().flatten() {
+      output.push_str("\n************************\n");
+      let fields = [
+        ("request id", request.request_id.clone().unwrap_or_else(|| "-".to_string())),
+        ("start time", clf_time(request.start_time)),
+        ("request duration", request.duration.as_micros().to_string()),
+        ("request method", request.method.clone()),
+        ("request URI", request.uri.clone()),
+        ("script", request.script.clone()),
+        ("memory", request.memory.to_string()),
+      ];
+      text_fields(&mut output, &fields);
+    }
+    output
+  }
+
+  fn json(&self) -> String {
+    let metrics = &self.metrics;
+    let requests = self.requests.as_ref().map(|requests| {
+      requests
+        .iter()
+        .map(|request| JsonRequest {
+          request_id: request.request_id.as_deref(),
+          method: &request.method,
+          uri: &request.uri,
+          script: &request.script,
+          start_time: rfc3339_time(request.start_time),
+          duration: request.duration.as_secs_f64(),
+          memory: request.memory,
+        })
+        .collect()
+    });
+    let status = JsonStatus {
+      start_time: rfc3339_time(self.start_time),
+      start_since: self.start_since.as_secs(),
+      accepted_connections: metrics.accepted_connections,
+      php_queue: metrics.php_queued,
+      max_php_queue: metrics.php_max_queued,
+      idle_php_threads: self.idle_php_threads(),
+      active_php_threads: metrics.php_active,
+      total_php_threads: metrics.php_threads,
+      max_active_php_threads: metrics.php_max_active,
+      slow_requests: metrics.slow_requests,
+      requests,
+    };
+    serde_json::to_string(&status).unwrap_or_default()
+  }
+
+  fn openmetrics(&self) -> String {
+    let metrics = &self.metrics;
+    let mut output = String::new();
+    let start_time = self.start_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+
+    header(&mut output, "pasir_start_time_seconds", "gauge", "Time the server started at.");
+    let _ = writeln!(output, "pasir_start_time_seconds {start_time}");
+    header(&mut output, "pasir_accepted_connections", "counter", "Client connections accepted.");
+    let _ = writeln!(output, "pasir_accepted_connections_total {}", metrics.accepted_connections);
+    header(&mut output, "pasir_php_queue", "gauge", "PHP executions waiting for a thread.");
+    let _ = writeln!(output, "pasir_php_queue {}", metrics.php_queued);
+    header(&mut output, "pasir_php_max_queue", "gauge", "Most PHP executions waiting for a thread at once.");
+    let _ = writeln!(output, "pasir_php_max_queue {}", metrics.php_max_queued);
+    header(&mut output, "pasir_php_threads", "gauge", "PHP threads by state.");
+    let _ = writeln!(output, "pasir_php_threads{{state=\"idle\"}} {}", self.idle_php_threads());
+    let _ = writeln!(output, "pasir_php_threads{{state=\"active\"}} {}", metrics.php_active);
+    header(&mut output, "pasir_php_max_active_threads", "gauge", "Most PHP threads active at once.");
+    let _ = writeln!(output, "pasir_php_max_active_threads {}", metrics.php_max_active);
+    header(&mut output, "pasir_slow_requests", "counter", "PHP executions that exceeded the slowlog timeout.");
+    let _ = writeln!(output, "pasir_slow_requests_total {}", metrics.slow_requests);
+
+    if let Some(requests) = &self.requests {
+      let labels: Vec<_> = requests
+        .iter()
+        .map(|request| {
+          format!(
+            "request_id=\"{}\",method=\"{}\",uri=\"{}\",script=\"{}\"",
+            escape_label(request.request_id.as_deref().unwrap_or_default()),
+            escape_label(&request.method),
+            escape_label(&request.uri),
+            escape_label(&request.script),
+          )
+        })
+        .collect();
+      let name = "pasir_php_request_duration_seconds";
+      header(&mut output, name, "gauge", "Time elapsed since the in-flight PHP request started.");
+      for (request, labels) in requests.iter().zip(&labels) {
+        let _ = writeln!(output, "{name}{{{labels}}} {}", request.duration.as_secs_f64());
+      }
+      let name = "pasir_php_request_memory_bytes";
+      header(&mut output, name, "gauge", "Memory used by the in-flight PHP request, as last sampled.");
+      for (request, labels) in requests.iter().zip(&labels) {
+        let _ = writeln!(output, "{name}{{{labels}}} {}", request.memory);
+      }
+    }
+
+    output.push_str("# EOF\n");
+    output
+  }
+}
+
+fn text_fields(output: &mut String, fields: &[(&str, String)]) {
+  for (name, value) in fields {
+    let _ = writeln!(output, "{:<24}{value}", format!("{name}:"));
+  }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(output, "# HELP {name} {help}");
+  let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+fn escape_label(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use std::time::UNIX_EPOCH;
+
+  use rstest::rstest;
+
+  use crate::metrics::Snapshot;
+  use crate::status::Format;
+  use crate::status::InFlightRequest;
+  use crate::status::Status;
+  use crate::status::parse_query;
+
+  fn status() -> Status {
+    Status {
+      start_time: UNIX_EPOCH + Duration::from_secs(971_185_336),
+      start_since: Duration::from_secs(42),
+      metrics: Snapshot {
+        accepted_connections: 10,
+        php_threads: 4,
+        php_queued: 0,
+        php_max_queued: 2,
+        php_active: 1,
+        php_max_active: 3,
+        slow_requests: 1,
+      },
+      requests: Some(vec![InFlightRequest {
+        request_id: Some("f81d4fae".to_string()),
+        method: "GET".to_string(),
+        uri: "/slow.php?name=\"foo\"".to_string(),
+        script: "/srv/www/slow.php".to_string(),
+        start_time: UNIX_EPOCH + Duration::from_secs(971_185_376),
+        duration: Duration::from_millis(1500),
+        memory: 2_097_152,
+      }]),
+    }
+  }
+
+  #[rstest]
+  #[case(None, Format::Text, false)]
+  #[case(Some("full"), Format::Text, true)]
+  #[case(Some("json&full"), Format::Json, true)]
+  #[case(Some("openmetrics"), Format::OpenMetrics, false)]
+  #[case(Some("full=1&json="), Format::Json, true)]
+  fn test_parse_query(#[case] query: Option<&str>, #[case] format: Format, #[case] full: bool) {
+    assert_eq!(parse_query(query), (format, full));
+  }
+
+  #[test]
+  fn test_text() {
+    assert_eq!(
+      status().text(),
+      "start time:             10/Oct/2000:13:42:16 +0000
+start since:            42
+accepted conn:          10
+php queue:              0
+max php queue:          2
+idle php threads:       3
+active php threads:     1
+total php threads:      4
+max active php threads: 3
+slow requests:          1
+
+************************
+request id:             f81d4fae
+start time:             10/Oct/2000:13:42:56 +0000
+request duration:       1500000
+request method:         GET
+request URI:            /slow.php?name=\"foo\"
+script:                 /srv/www/slow.php
+memory:                 2097152
+"
+    );
+  }
+
+  #[test]
+  fn test_json() {
+    let status = Status {
+      requests: None,
+      ..status()
+    };
+    assert_eq!(
+      status.json(),
+      "{\"start_time\":\"2000-10-10T13:42:16.000Z\",\"start_since\":42,\"accepted_connections\":10,\"php_queue\":0,\"max_php_queue\":2,\"idle_php_threads\":3,\"active_php_threads\":1,\"total_php_threads\":4,\"max_active_php_threads\":3,\"slow_requests\":1}"
+    );
+    assert!(status().json().ends_with(
+      ",\"requests\":[{\"request_id\":\"f81d4fae\",\"method\":\"GET\",\"uri\":\"/slow.php?name=\\\"foo\\\"\",\"script\":\"/srv/www/slow.php\",\"start_time\":\"2000-10-10T13:42:56.000Z\",\"duration\":1.5,\"memory\":2097152}]}"
+    ));
+  }
+
+  #[test]
+  fn test_openmetrics() {
+    let output = status().openmetrics();
+    assert!(output.contains("# TYPE pasir_accepted_connections counter\n"));
+    assert!(output.contains("\npasir_accepted_connections_total 10\n"));
+    assert!(output.contains("\npasir_php_threads{state=\"idle\"} 3\n"));
+    assert!(output.contains(
+      "\npasir_php_request_duration_seconds{request_id=\"f81d4fae\",method=\"GET\",uri=\"/slow.php?name=\\\"foo\\\"\",script=\"/srv/www/slow.php\"} 1.5\n"
+    ));
+    assert!(output.ends_with("\n# EOF\n"));
+  }
+}
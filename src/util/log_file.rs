@@ -0,0 +1,182 @@
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tokio::signal::unix::SignalKind;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Lines queued for writing, beyond which new lines are dropped.
+const CAPACITY: usize = 8192;
+
+/// Lines written between two flushes.
+const BATCH: usize = 256;
+
+/// Lines appended to a file, or to stdout for `-`, by a dedicated thread.
+///
+/// Lines are dropped, and counted, while the queue is full rather than buffered without bound. The file
+/// is reopened on `SIGUSR1`, so it can be rotated by logrotate.
+#[derive(Clone, Debug)]
+pub(crate) struct LogFile {
+  tx: Sender<String>,
+  dropped: Arc<AtomicU64>,
+}
+
+impl LogFile {
+  /// Opens `path` for appending and spawns the thread writing the lines.
+  pub(crate) fn open(path: PathBuf) -> anyhow::Result<Self> {
+    let output = open(&path)?;
+    let mut sigusr1 = tokio::signal::unix::signal(SignalKind::user_defined1())?;
+    let (tx, rx) = tokio::sync::mpsc::channel::<String>(CAPACITY);
+    let dropped = Arc::new(AtomicU64::default());
+    let reopen = Arc::new(AtomicBool::default());
+
+    let reopen_requested = reopen.clone();
+    tokio::spawn(async move {
+      while sigusr1.recv().await.is_some() {
+        reopen_requested.store(true, Ordering::Relaxed);
+      }
+    });
+    let writer_dropped = dropped.clone();
+    std::thread::Builder::new()
+      .name("pasir-log".to_string())
+      .spawn(move || write_lines(&path, output, rx, &reopen, &writer_dropped))?;
+
+    Ok(Self { tx, dropped })
+  }
+
+  #[cfg(test)]
+  pub(crate) fn channel() -> (Self, Receiver<String>) {
+    let (tx, rx) = tokio::sync::mpsc::channel(CAPACITY);
+    (
+      Self {
+        tx,
+        dropped: Arc::default(),
+      },
+      rx,
+    )
+  }
+
+  /// Queues `line`, which includes its trailing newline, for writing.
+  pub(crate) fn write(&self, line: String) {
+    if self.tx.try_send(line).is_err() {
+      self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+}
+
+/// Writes the queued lines by batches until every `LogFile` is dropped, reopening the output when
+/// `reopen` is set by `SIGUSR1`.
+fn write_lines(path: &Path, output: Box<dyn Write + Send>, mut rx: Receiver<String>, reopen: &AtomicBool, dropped: &AtomicU64) {
+  let mut output = BufWriter::new(output);
+  while let Some(line) = rx.blocking_recv() {
+    if reopen.swap(false, Ordering::Relaxed) {
+      let _ = output.flush();
+      match open(path) {
+        Ok(reopened) => {
+          output = BufWriter::new(reopened);
+          info!("Reopened {}", path.display());
+        }
+        Err(e) => error!("Failed to reopen {}: {e}", path.display()),
+      }
+    }
+    let dropped = dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+      warn!("Dropped {dropped} lines of {}, written slower than logged", path.display());
+    }
+
+    let batch = std::iter::once(line).chain(std::iter::from_fn(|| rx.try_recv().ok()).take(BATCH - 1));
+    let result = batch
+      .map(|line| output.write_all(line.as_bytes()))
+      .collect::<std::io::Result<()>>()
+      .and_then(|_| output.flush());
+    if let Err(e) = result {
+      error!("Failed to write to {}: {e}", path.display());
+    }
+  }
+}
+
+fn open(path: &Path) -> std::io::Result<Box<dyn Write + Send>> {
+  if path == Path::new("-") {
+    return Ok(Box::new(std::io::stdout()));
+  }
+  Ok(Box::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+/// UTC date and time of day of `time` as `(year, month, day, hours, minutes, seconds, milliseconds)`.
+fn civil(time: SystemTime) -> (i64, usize, u64, u64, u64, u64, u32) {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let seconds = since_epoch.as_secs();
+  let (days, rest) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+  // Days to civil date, from http://howardhinnant.github.io/date_algorithms.html.
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+  let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as usize;
+  let year = yoe + era * 400 + i64::from(month <= 2);
+
+  (year, month, day, rest / 3_600, rest % 3_600 / 60, rest % 60, since_epoch.subsec_millis())
+}
+
+pub(crate) fn clf_time(time: SystemTime) -> String {
+  let (year, month, day, hours, minutes, seconds, _) = civil(time);
+  format!("{day:02}/{}/{year}:{hours:02}:{minutes:02}:{seconds:02} +0000", MONTHS[month - 1])
+}
+
+pub(crate) fn rfc3339_time(time: SystemTime) -> String {
+  let (year, month, day, hours, minutes, seconds, millis) = civil(time);
+  format!("{year}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}.{millis:03}Z")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use std::time::SystemTime;
+
+  use std::sync::atomic::Ordering;
+
+  use rstest::rstest;
+
+  use crate::util::log_file::CAPACITY;
+  use crate::util::log_file::LogFile;
+  use crate::util::log_file::clf_time;
+  use crate::util::log_file::rfc3339_time;
+
+  #[rstest]
+  #[case(0, "01/Jan/1970:00:00:00 +0000", "1970-01-01T00:00:00.000Z")]
+  #[case(951_782_400, "29/Feb/2000:00:00:00 +0000", "2000-02-29T00:00:00.000Z")]
+  #[case(1_792_324_799, "18/Oct/2026:11:59:59 +0000", "2026-10-18T11:59:59.000Z")]
+  fn test_time(#[case] seconds: u64, #[case] clf: &str, #[case] rfc3339: &str) {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+    assert_eq!(clf_time(time), clf);
+    assert_eq!(rfc3339_time(time), rfc3339);
+  }
+
+  #[test]
+  fn test_write_full() {
+    let (file, mut rx) = LogFile::channel();
+    for _ in 0..CAPACITY + 2 {
+      file.write("line\n".to_string());
+    }
+    assert_eq!(file.dropped.load(Ordering::Relaxed), 2);
+    assert_eq!(rx.try_recv().unwrap(), "line\n");
+  }
+}
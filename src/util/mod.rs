@@ -1,3 +1,4 @@
+pub(crate) mod log_file;
 pub(crate) mod response_ext;
 
 #[macro_export]
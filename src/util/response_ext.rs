@@ -6,6 +6,7 @@ use hyper::StatusCode;
 pub(crate) trait ResponseExt<T> {
   fn bad_request(body: T) -> Result<Response<T>, Infallible>;
   fn internal_server_error(body: T) -> Result<Response<T>, Infallible>;
+  fn bad_gateway(body: T) -> Result<Response<T>, Infallible>;
   fn service_unavailable(body: T) -> Result<Response<T>, Infallible>;
   #[cfg(not(php_zend_max_execution_timers))]
   fn gateway_timeout(body: T) -> Result<Response<T>, Infallible>;
@@ -20,6 +21,10 @@ impl<T> ResponseExt<T> for Response<T> {
     Ok(make_response(StatusCode::INTERNAL_SERVER_ERROR, body))
   }
 
+  fn bad_gateway(body: T) -> Result<Self, Infallible> {
+    Ok(make_response(StatusCode::BAD_GATEWAY, body))
+  }
+
   fn service_unavailable(body: T) -> Result<Self, Infallible> {
     Ok(make_response(StatusCode::SERVICE_UNAVAILABLE, body))
   }
@@ -49,6 +54,7 @@ mod tests {
   #[rstest]
   #[case::bad_request(Response::bad_request, StatusCode::BAD_REQUEST)]
   #[case::internal_server_error(Response::internal_server_error, StatusCode::INTERNAL_SERVER_ERROR)]
+  #[case::bad_gateway(Response::bad_gateway, StatusCode::BAD_GATEWAY)]
   #[case::service_unavailable(Response::service_unavailable, StatusCode::SERVICE_UNAVAILABLE)]
   fn test_response_ext<F: Fn(String) -> Result<Response<String>, Infallible>>(#[case] f: F, #[case] status: StatusCode) {
     let response = f("Foo".to_string());
@@ -0,0 +1,5 @@
+<?php
+
+echo 'foo';
+flush();
+echo 'bar';